@@ -0,0 +1,22 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::{env, fs, path::Path};
+
+/// Version of the finch crate resolved in Cargo.lock, for run reports
+fn finch_version() -> Option<String> {
+    let lock = Path::new(&env::var("CARGO_MANIFEST_DIR").ok()?).join("Cargo.lock");
+    let lock = fs::read_to_string(lock).ok()?;
+    let mut lines = lock.lines();
+    lines.find(|line| *line == "name = \"finch\"")?;
+    let version = lines.next()?.strip_prefix("version = \"")?;
+    Some(version.trim_end_matches('"').to_string())
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    let version = finch_version().unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=CEDAR_FINCH_VERSION={}", version);
+}
@@ -0,0 +1,225 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use crate::tree::{self, Tree};
+
+/// Options controlling the terminal rendering of a tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsciiOptions {
+    /// Total width of the drawing, labels included
+    pub width: usize,
+    /// Use Unicode box-drawing characters instead of plain ASCII
+    pub unicode: bool,
+    /// Draw support values of internal nodes
+    pub support: bool,
+}
+
+impl Default for AsciiOptions {
+    fn default() -> Self {
+        AsciiOptions {
+            width: terminal_width(),
+            unicode: true,
+            support: false,
+        }
+    }
+}
+
+/// Width of the terminal, overridden by the `COLUMNS` environment variable;
+/// 80 when neither is known
+pub fn terminal_width() -> usize {
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|c| c.parse().ok())
+        .filter(|&c| c > 0)
+        .or_else(|| {
+            let (_, columns) = console::Term::stderr().size_checked()?;
+            Some(usize::from(columns)).filter(|&c| c > 0)
+        })
+        .unwrap_or(80)
+}
+
+/// Draw `tree` as a rectangular phylogram with one line per leaf.
+///
+/// Branch lengths are scaled to the available width; every branch takes at
+/// least one column so that the topology stays readable.
+pub fn render_ascii(tree: &Tree, options: &AsciiOptions) -> String {
+    let leaves = tree.leaves();
+    let label_width = leaves
+        .iter()
+        .map(|&id| tree.node(id).label.as_deref().unwrap_or("").chars().count())
+        .max()
+        .unwrap_or(0);
+    let draw_width = options.width.saturating_sub(label_width + 2).max(10);
+
+    let depth = tree.node_depths();
+    let max_depth = depth.iter().cloned().fold(0.0, f64::max);
+    let scale = if max_depth > 0.0 {
+        (draw_width - 1) as f64 / max_depth
+    } else {
+        0.0
+    };
+
+    let mut col = vec![0usize; tree.len()];
+    for id in tree.preorder() {
+        if let Some(parent) = tree.node(id).parent {
+            col[id] = ((depth[id] * scale).round() as usize).max(col[parent] + 1);
+        }
+    }
+
+    let mut row = vec![0usize; tree.len()];
+    for (i, &id) in leaves.iter().enumerate() {
+        row[id] = i;
+    }
+    for id in tree.postorder() {
+        let children = &tree.node(id).children;
+        if let (Some(&first), Some(&last)) = (children.first(), children.last()) {
+            row[id] = (row[first] + row[last]) / 2;
+        }
+    }
+
+    let total_width = col.iter().max().copied().unwrap_or(0) + label_width + 3;
+    let mut grid = vec![vec![' '; total_width]; leaves.len().max(1)];
+    let (hline, vline) = if options.unicode {
+        ('─', '│')
+    } else {
+        ('-', '|')
+    };
+
+    for id in tree.preorder() {
+        let node = tree.node(id);
+        // Branch leading to this node
+        if let Some(parent) = node.parent {
+            let end = if node.is_leaf() { col[id] + 1 } else { col[id] };
+            for cell in &mut grid[row[id]][(col[parent] + 1)..end] {
+                *cell = hline;
+            }
+        }
+        if node.is_leaf() {
+            let label = node.label.as_deref().unwrap_or("");
+            for (i, ch) in label.chars().enumerate() {
+                grid[row[id]][col[id] + 2 + i] = ch;
+            }
+            continue;
+        }
+
+        // Vertical line joining the children
+        let top = row[node.children[0]];
+        let bottom = row[*node.children.last().unwrap()];
+        for (r, line) in grid.iter_mut().enumerate().take(bottom + 1).skip(top) {
+            let up = r > top;
+            let down = r < bottom;
+            let left = r == row[id] && node.parent.is_some();
+            let right = node.children.iter().any(|&c| row[c] == r);
+            line[col[id]] = junction(up, down, left, right, options.unicode, vline);
+        }
+
+        if let (true, Some(support), Some(parent)) = (options.support, node.support, node.parent) {
+            let text = format!("{}", support);
+            let len = text.chars().count();
+            if col[id] > col[parent] + len {
+                for (i, ch) in text.chars().enumerate() {
+                    grid[row[id]][col[id] - len + i] = ch;
+                }
+            }
+        }
+    }
+
+    let mut lines: Vec<String> = grid
+        .into_iter()
+        .map(|line| line.into_iter().collect::<String>().trim_end().to_string())
+        .collect();
+    if let Some(bar) = scale_bar(max_depth, scale, options.unicode) {
+        lines.push(String::new());
+        lines.push(bar);
+    }
+    lines.join("\n") + "\n"
+}
+
+fn junction(up: bool, down: bool, left: bool, right: bool, unicode: bool, vline: char) -> char {
+    if !unicode {
+        return if (up || down) && !left && !right {
+            vline
+        } else if !up && !down {
+            '-'
+        } else {
+            '+'
+        };
+    }
+    match (up, down, left, right) {
+        (false, true, false, _) => '┌',
+        (false, true, true, _) => '┬',
+        (true, false, false, _) => '└',
+        (true, false, true, _) => '┴',
+        (true, true, true, true) => '┼',
+        (true, true, false, true) => '├',
+        (true, true, true, false) => '┤',
+        (true, true, false, false) => vline,
+        (false, false, _, _) => '─',
+    }
+}
+
+/// Scale bar showing a round branch length, see [`tree::scale_bar_length`]
+fn scale_bar(max_depth: f64, scale: f64, unicode: bool) -> Option<String> {
+    let (value, label) = tree::scale_bar_length(max_depth).filter(|_| scale > 0.0)?;
+    let columns = ((value * scale).round() as usize).max(1);
+    let (left, line, right) = if unicode {
+        ('├', '─', '┤')
+    } else {
+        ('|', '-', '|')
+    };
+    let body = line.to_string().repeat(columns.saturating_sub(1));
+    Some(format!("{}{}{} {}", left, body, right, label))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_ascii_unicode() {
+        let tree = Tree::from_newick("((A:1,B:1):1,C:2);").unwrap();
+        let options = AsciiOptions {
+            width: 12,
+            unicode: true,
+            support: false,
+        };
+        let drawing = render_ascii(&tree, &options);
+        let lines: Vec<&str> = drawing.lines().collect();
+        assert_eq!(lines[0], "┌────┬──── A");
+        assert_eq!(lines[1], "│    └──── B");
+        assert_eq!(lines[2], "└───────── C");
+        assert_eq!(lines[4], "├┤ 0.2");
+    }
+
+    #[test]
+    fn test_render_ascii_plain_with_support() {
+        let tree = Tree::from_newick("((A:1,B:1)95:3,C:4);").unwrap();
+        let options = AsciiOptions {
+            width: 14,
+            unicode: false,
+            support: true,
+        };
+        let drawing = render_ascii(&tree, &options);
+        assert_eq!(
+            drawing,
+            "+-----95+-- A\n|       +-- B\n+---------- C\n\n|| 0.5\n"
+        );
+    }
+
+    #[test]
+    fn test_render_ascii_cladogram() {
+        let tree = Tree::from_newick("((A,B),(C,D));").unwrap();
+        let options = AsciiOptions {
+            width: 20,
+            ..Default::default()
+        };
+        let drawing = render_ascii(&tree, &options);
+        let lines: Vec<&str> = drawing.lines().collect();
+        assert_eq!(lines[0], "┌───────┬──────── A");
+        assert_eq!(lines[1], "│       └──────── B");
+        assert_eq!(lines[2], "└───────┬──────── C");
+        assert_eq!(lines[3], "        └──────── D");
+    }
+}
@@ -3,7 +3,31 @@
 // This file may not be copied, modified, or distributed except according
 // to those terms.
 
-use clap::Parser;
+use clap::{ArgAction, Args, Parser, Subcommand};
+
+use cedar::cluster::Linkage;
+use cedar::derep::Criterion;
+use cedar::formats::TreeFormat;
+use cedar::index::DEFAULT_CANDIDATES;
+use cedar::inputs::FASTA_EXTENSIONS;
+use cedar::pipeline::OnError;
+use cedar::place::Weighting;
+use cedar::sketch::SketchFormat;
+use cedar::stats::StatsFormat;
+use cedar::svg::Layout;
+use cedar::tree::NegativeBranches;
+use cedar::units::parse_size;
+
+/// Exit codes, see `cedar::Error::exit_code`
+const EXIT_CODES: &str = "Exit codes:
+  0  success
+  1  unexpected error
+  2  invalid command line
+  3  invalid input (missing genomes, not FASTA, size outliers...)
+  4  file could not be read or written
+  5  sketching failed
+  6  distances could not be computed or read
+  7  tree could not be built, read or compared";
 
 #[derive(Parser, Debug)]
 #[command(
@@ -11,17 +35,39 @@ use clap::Parser;
     about = "Compute (rapid) neighbor joining tree from sequences",
     author,
     version,
-    arg_required_else_help = true
+    arg_required_else_help = true,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    after_help = EXIT_CODES
 )]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Fasta file(s) to build trees [suports .gz, .xz, .bz2]
-    #[arg(required = true)]
+    #[arg(required_unless_present_any = ["fofn", "input_dir"])]
     pub input: Vec<String>,
 
-    /// Output tree (Newick format) to FILE
+    #[command(flatten)]
+    pub source: InputSourceArgs,
+
+    /// Output tree to FILE
     #[arg(short, value_name = "FILE")]
     pub output: Option<String>,
 
+    /// Output tree format
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = TreeFormat::Newick,
+        value_name = "FORMAT"
+    )]
+    pub tree_format: TreeFormat,
+
+    /// Annotate leaves with their genome size
+    #[arg(long)]
+    pub annotate: bool,
+
     /// Keep sketches and distance files
     #[arg(short = 'K')]
     pub keep: bool,
@@ -30,46 +76,614 @@ pub struct Cli {
     #[arg(short, default_value_t = 1, value_name = "INT")]
     pub threads: usize,
 
-    /// Sketch size
+    #[command(flatten)]
+    pub sketch: SketchArgs,
+
+    #[command(flatten)]
+    pub stats: StatsArgs,
+
+    #[command(flatten)]
+    pub filter: FilterArgs,
+
+    /// What to do with genomes that cannot be read or are not valid FASTA
+    #[arg(long, value_enum, default_value_t = OnError::Abort, value_name = "POLICY")]
+    pub on_error: OnError,
+
+    /// Write the genomes skipped with --on-error skip and why (TSV) to FILE
+    #[arg(long, value_name = "FILE")]
+    pub failures: Option<String>,
+
+    /// Write a JSON report of the run (versions, parameters, genomes,
+    /// timings, output checksums) to FILE
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<String>,
+
+    /// Compute canonical NJ tree
+    #[arg(short = 'c', help_heading = "Tree options")]
+    pub canonical: bool,
+
+    /// Number of decimals for branch lengths [default: shortest representation]
+    #[arg(long, value_name = "INT", help_heading = "Tree options")]
+    pub precision: Option<usize>,
+
+    /// Policy for negative branch lengths
     #[arg(
-        short = 's',
         long,
-        default_value_t = 1000,
-        value_name = "INT",
-        help_heading = "Sketching options"
+        value_enum,
+        default_value_t = NegativeBranches::Keep,
+        value_name = "POLICY",
+        help_heading = "Tree options"
+    )]
+    pub negative_branches: NegativeBranches,
+
+    /// With several k-mer sizes, write the matrix and tree of each one to DIR
+    #[arg(long, value_name = "DIR", help_heading = "Tree options")]
+    pub per_k: Option<String>,
+
+    /// Draw the resulting tree in the terminal (on stderr)
+    #[arg(long, help_heading = "Tree options")]
+    pub print_tree: bool,
+
+    /// Also draw the resulting tree as SVG to FILE
+    #[arg(long, value_name = "FILE", help_heading = "Rendering options")]
+    pub svg: Option<String>,
+
+    #[command(flatten)]
+    pub render: SvgArgs,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+/// Other ways to give genomes than listing them on the command line
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Input options")]
+pub struct InputSourceArgs {
+    /// Read genome paths from FILE, one per line, optionally followed by a
+    /// tab and a label naming the genome in outputs
+    #[arg(long, value_name = "FILE")]
+    pub fofn: Option<String>,
+
+    /// Read the genomes of DIR
+    #[arg(long, value_name = "DIR")]
+    pub input_dir: Option<String>,
+
+    /// Also read the subdirectories of --input-dir
+    #[arg(short = 'R', long, requires = "input_dir")]
+    pub recursive: bool,
+
+    /// Comma-separated extensions of the genomes read from --input-dir,
+    /// compressed or not
+    #[arg(
+        long,
+        value_name = "EXT",
+        value_delimiter = ',',
+        default_values = FASTA_EXTENSIONS
     )]
+    pub extension: Vec<String>,
+}
+
+/// Options shared by every command that sketches sequences
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Sketching options")]
+pub struct SketchArgs {
+    /// Sketch size
+    #[arg(short = 's', long, default_value_t = 1000, value_name = "INT")]
     pub size: usize,
 
     /// Seed for the hash function
+    #[arg(short = 'S', long, default_value_t = 42, value_name = "INT")]
+    pub seed: u64,
+
+    /// K-mer size, or comma-separated sizes to compare (e.g. 16,21,31)
+    #[arg(short = 'k', long, value_name = "INT", value_delimiter = ',')]
+    pub kmer: Vec<u8>,
+
+    /// Amount of extra scketching before filtering
+    #[arg(short = 'x', long, default_value_t = 200, value_name = "INT")]
+    pub oversketch: usize,
+
+    /// Sketch amino-acid k-mers: protein FASTA is used as is, genomes are
+    /// translated in six frames
+    #[arg(long)]
+    pub protein: bool,
+
+    /// Keep one hash out of INT (FracMinHash) instead of a fixed sketch size
     #[arg(
-        short = 'S',
         long,
-        default_value_t = 42,
         value_name = "INT",
-        help_heading = "Sketching options"
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with = "size"
     )]
-    pub seed: u64,
+    pub scaled: Option<u64>,
 
-    /// K-mer size
+    /// Genome size used to compute k instead of the mean genome size
+    /// (e.g. 5M, 4.2Mb)
     #[arg(
-        short = 'k',
         long,
-        value_name = "INT",
-        help_heading = "Sketching options"
+        value_name = "SIZE",
+        value_parser = parse_size,
+        conflicts_with = "kmer"
     )]
-    pub kmer: Option<u8>,
+    pub genome_size: Option<usize>,
+}
 
-    /// Amount of extra scketching before filtering
+/// Genomes left out of the analysis
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Filtering options")]
+pub struct FilterArgs {
+    /// Leave out genomes shorter than SIZE (e.g. 500K, 1.5Mb)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub min_genome_size: Option<usize>,
+
+    /// Leave out genomes longer than SIZE (e.g. 15M)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub max_genome_size: Option<usize>,
+
+    /// Leave out genomes with a larger fraction of N bases (0 to 1)
+    #[arg(long, value_name = "FLOAT", value_parser = parse_fraction)]
+    pub max_n_fraction: Option<f64>,
+
+    /// Drop contigs shorter than SIZE before sketching (e.g. 500, 1K)
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    pub min_contig_length: Option<usize>,
+
+    /// Mask low-complexity regions (DUST) before sketching
+    #[arg(long)]
+    pub mask_low_complexity: bool,
+
+    /// Write the filter decision of each genome (TSV), flagging genome size
+    /// outliers, to FILE
+    #[arg(long, value_name = "FILE")]
+    pub filter_report: Option<String>,
+}
+
+/// Parse a fraction between 0 and 1
+fn parse_fraction(text: &str) -> Result<f64, String> {
+    match text.parse::<f64>() {
+        Ok(value) if (0.0..=1.0).contains(&value) => Ok(value),
+        _ => Err(format!("'{}' is not a fraction between 0 and 1", text)),
+    }
+}
+
+/// Per-genome statistics table written alongside the main output
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Statistics options")]
+pub struct StatsArgs {
+    /// Write per-genome assembly statistics to FILE
+    #[arg(id = "stats", long = "stats", value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Format of the statistics table
     #[arg(
-        short = 'x',
         long,
-        default_value_t = 200,
-        value_name = "INT",
-        help_heading = "Sketching options"
+        value_enum,
+        default_value_t = StatsFormat::Tsv,
+        value_name = "FORMAT",
+        requires = "stats"
     )]
-    pub oversketch: usize,
+    pub stats_format: StatsFormat,
+}
+
+/// Options shared by every SVG rendering
+#[derive(Args, Debug)]
+#[command(next_help_heading = "Rendering options")]
+pub struct SvgArgs {
+    /// SVG tree layout
+    #[arg(long, value_enum, default_value_t = Layout::Rectangular, value_name = "LAYOUT")]
+    pub layout: Layout,
+
+    /// Figure width in pixels
+    #[arg(long, default_value_t = 800.0, value_name = "PX")]
+    pub svg_width: f64,
+
+    /// Metadata TSV whose first column holds the leaf labels
+    #[arg(long, value_name = "FILE", requires = "color_by")]
+    pub metadata: Option<String>,
+
+    /// Metadata column used to color the leaves
+    #[arg(long, value_name = "COLUMN", requires = "metadata")]
+    pub color_by: Option<String>,
+
+    /// Show support values of internal nodes
+    #[arg(long)]
+    pub show_support: bool,
+}
+
+/// Verbosity of log messages and progress bars
+#[derive(Args, Debug, Clone, Copy, Default)]
+#[command(next_help_heading = "Logging options")]
+pub struct LogArgs {
+    /// Log more details (-v: debug, -vv: trace)
+    #[arg(short, long, action = ArgAction::Count, conflicts_with = "quiet")]
+    pub verbose: u8,
+
+    /// Only log warnings and errors, without progress bars
+    #[arg(short, long)]
+    pub quiet: bool,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Draw a Newick tree in the terminal
+    View(ViewArgs),
+    /// Draw a Newick tree as SVG
+    Render(RenderArgs),
+    /// Cluster genomes at a distance or ANI threshold
+    Cluster(ClusterArgs),
+    /// Keep one representative genome per group of near-identical genomes
+    Dereplicate(DerepArgs),
+    /// Place new genomes into an existing tree
+    Place(PlaceArgs),
+    /// Compute distances between query and reference genomes only
+    Dist(DistArgs),
+    /// Find the nearest reference genomes in a large sketch collection
+    Search(SearchArgs),
+    /// Sketch genomes into sketch files or a sketch stream on stdout
+    Sketch(SketchCommandArgs),
+    /// Report per-genome assembly statistics
+    Info(InfoArgs),
+}
+
+impl Command {
+    pub fn log(&self) -> LogArgs {
+        match self {
+            Command::View(args) => args.log,
+            Command::Render(args) => args.log,
+            Command::Cluster(args) => args.log,
+            Command::Dereplicate(args) => args.log,
+            Command::Place(args) => args.log,
+            Command::Dist(args) => LogArgs {
+                verbose: args.verbose,
+                quiet: args.quiet,
+            },
+            Command::Search(args) => args.log,
+            Command::Sketch(args) => args.log,
+            Command::Info(args) => args.log,
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct RenderArgs {
+    /// Newick tree file, '-' for stdin
+    pub tree: String,
+
+    /// Output SVG to FILE [default: stdout]
+    #[arg(short, value_name = "FILE")]
+    pub output: Option<String>,
+
+    #[command(flatten)]
+    pub svg: SvgArgs,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ViewArgs {
+    /// Newick tree file, '-' for stdin
+    pub tree: String,
+
+    /// Drawing width [default: $COLUMNS, or the terminal width]
+    #[arg(short, long, value_name = "INT")]
+    pub width: Option<usize>,
+
+    /// Use plain ASCII characters instead of Unicode box drawing
+    #[arg(long)]
+    pub ascii: bool,
+
+    /// Show support values of internal nodes
+    #[arg(long)]
+    pub support: bool,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct ClusterArgs {
+    /// Sequence files, sketch files (.msh, .sk, .bsk) or a PHYLIP distance matrix
+    #[arg(required_unless_present_any = ["fofn", "input_dir"])]
+    pub input: Vec<String>,
+
+    #[command(flatten)]
+    pub source: InputSourceArgs,
+
+    /// Output cluster assignments (TSV) to FILE
+    #[arg(short, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Number of threads to use
+    #[arg(short, default_value_t = 1, value_name = "INT")]
+    pub threads: usize,
+
+    /// Linkage criterion
+    #[arg(short, long, value_enum, default_value_t = Linkage::Average)]
+    pub linkage: Linkage,
+
+    /// Maximum Mash distance within a cluster
+    #[arg(
+        short = 'd',
+        long,
+        value_name = "FLOAT",
+        required_unless_present = "ani",
+        conflicts_with = "ani"
+    )]
+    pub threshold: Option<f64>,
+
+    /// Minimum ANI (in percent) within a cluster, e.g. 95 for species
+    #[arg(short, long, value_name = "FLOAT")]
+    pub ani: Option<f64>,
+
+    #[command(flatten)]
+    pub sketch: SketchArgs,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct DerepArgs {
+    /// Fasta file(s) to dereplicate [suports .gz, .xz, .bz2]
+    #[arg(required_unless_present_any = ["fofn", "input_dir"])]
+    pub input: Vec<String>,
+
+    #[command(flatten)]
+    pub source: InputSourceArgs,
+
+    /// Output the list of representative files to FILE
+    #[arg(short, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Number of threads to use
+    #[arg(short, default_value_t = 1, value_name = "INT")]
+    pub threads: usize,
+
+    /// Linkage criterion
+    #[arg(short, long, value_enum, default_value_t = Linkage::Average)]
+    pub linkage: Linkage,
+
+    /// Maximum Mash distance within a group
+    #[arg(short = 'd', long, value_name = "FLOAT", conflicts_with = "ani")]
+    pub threshold: Option<f64>,
+
+    /// Minimum ANI (in percent) within a group
+    #[arg(short, long, default_value_t = 99.0, value_name = "FLOAT")]
+    pub ani: f64,
+
+    /// Assembly statistic used to pick representatives
+    #[arg(long, value_enum, default_value_t = Criterion::N50)]
+    pub select: Criterion,
+
+    /// Quality TSV (genome, score) used to pick representatives, higher is better
+    #[arg(long, value_name = "FILE")]
+    pub quality: Option<String>,
+
+    /// Output genome-to-group assignments (TSV) to FILE
+    #[arg(long, value_name = "FILE")]
+    pub clusters: Option<String>,
+
+    /// Build a NJ tree of the representatives and write it (Newick) to FILE
+    #[arg(long, value_name = "FILE")]
+    pub tree: Option<String>,
 
     /// Compute canonical NJ tree
-    #[arg(short = 'c', help_heading = "Tree options")]
+    #[arg(short = 'c', requires = "tree")]
     pub canonical: bool,
+
+    #[command(flatten)]
+    pub sketch: SketchArgs,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct PlaceArgs {
+    /// Fasta file(s) of the query genomes [suports .gz, .xz, .bz2]
+    #[arg(required = true)]
+    pub input: Vec<String>,
+
+    /// Reference tree (Newick) whose leaves are the reference genomes
+    #[arg(long, required = true, value_name = "FILE")]
+    pub tree: String,
+
+    /// Sketch files of the reference genomes
+    #[arg(short, long, required = true, num_args = 1.., value_name = "FILE")]
+    pub reference: Vec<String>,
+
+    /// Output the updated tree (Newick) to FILE
+    #[arg(short, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Output the placement report (TSV) to FILE [default: stderr]
+    #[arg(long, value_name = "FILE")]
+    pub report: Option<String>,
+
+    /// Least-squares weighting
+    #[arg(short, long, value_enum, default_value_t = Weighting::Fm)]
+    pub weighting: Weighting,
+
+    /// Amount of extra sketching before filtering, for query sequences
+    #[arg(short = 'x', long, default_value_t = 200, value_name = "INT")]
+    pub oversketch: usize,
+
+    /// Number of threads to use
+    #[arg(short, default_value_t = 1, value_name = "INT")]
+    pub threads: usize,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct DistArgs {
+    /// Query genomes, as sequence or sketch files
+    #[arg(short, long, required = true, num_args = 1.., value_name = "FILE")]
+    pub query: Vec<String>,
+
+    /// Reference genomes, as sequence or sketch files; more can be read
+    /// with --fofn and --input-dir
+    #[arg(
+        short,
+        long,
+        required_unless_present_any = ["fofn", "input_dir"],
+        num_args = 1..,
+        value_name = "FILE"
+    )]
+    pub reference: Vec<String>,
+
+    #[command(flatten)]
+    pub source: InputSourceArgs,
+
+    /// Output distances (TSV) to FILE [default: stdout]
+    #[arg(short, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Only report references within this Mash distance of the query
+    #[arg(short = 'd', long, value_name = "FLOAT")]
+    pub max_dist: Option<f64>,
+
+    /// Report the INT closest references of each query
+    #[arg(short = 'n', long, value_name = "INT")]
+    pub top: Option<usize>,
+
+    /// Number of threads to use
+    #[arg(short, default_value_t = 1, value_name = "INT")]
+    pub threads: usize,
+
+    #[command(flatten)]
+    pub sketch: SketchArgs,
+
+    /// Log more details (-v: debug, -vv: trace)
+    #[arg(
+        short,
+        long,
+        action = ArgAction::Count,
+        conflicts_with = "quiet",
+        help_heading = "Logging options"
+    )]
+    pub verbose: u8,
+
+    /// Only log warnings and errors, without progress bars (-q is --query)
+    #[arg(long, help_heading = "Logging options")]
+    pub quiet: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct SketchCommandArgs {
+    /// Fasta file(s) to sketch, '-' for stdin [suports .gz, .xz, .bz2]
+    #[arg(required_unless_present_any = ["fofn", "input_dir"])]
+    pub input: Vec<String>,
+
+    #[command(flatten)]
+    pub source: InputSourceArgs,
+
+    /// Write one sketch file per input in DIR
+    #[arg(
+        short,
+        value_name = "DIR",
+        default_value = ".",
+        conflicts_with = "stdout"
+    )]
+    pub outdir: String,
+
+    /// Write every sketch to stdout in a single stream
+    #[arg(long)]
+    pub stdout: bool,
+
+    /// Format of the stdout stream
+    #[arg(long, value_enum, default_value_t = SketchFormat::Mash, requires = "stdout")]
+    pub format: SketchFormat,
+
+    /// Sketch each record of the inputs as its own genome (requires -k)
+    #[arg(
+        long,
+        requires = "kmer",
+        conflicts_with_all = ["protein", "stats"]
+    )]
+    pub per_record: bool,
+
+    /// Number of threads to use
+    #[arg(short, default_value_t = 1, value_name = "INT")]
+    pub threads: usize,
+
+    #[command(flatten)]
+    pub sketch: SketchArgs,
+
+    #[command(flatten)]
+    pub stats: StatsArgs,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct InfoArgs {
+    /// Fasta file(s), '-' for stdin [suports .gz, .xz, .bz2]
+    #[arg(required_unless_present_any = ["fofn", "input_dir"])]
+    pub input: Vec<String>,
+
+    #[command(flatten)]
+    pub source: InputSourceArgs,
+
+    /// Output statistics to FILE [default: stdout]
+    #[arg(short, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Output format
+    #[arg(long, value_enum, default_value_t = StatsFormat::Tsv, value_name = "FORMAT")]
+    pub format: StatsFormat,
+
+    /// Number of threads to use
+    #[arg(short, default_value_t = 1, value_name = "INT")]
+    pub threads: usize,
+
+    #[command(flatten)]
+    pub sketch: SketchArgs,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct SearchArgs {
+    /// Query genomes, as sequence or sketch files
+    #[arg(required = true)]
+    pub input: Vec<String>,
+
+    /// Sketch files of the reference genomes
+    #[arg(short, long, required = true, num_args = 1.., value_name = "FILE")]
+    pub reference: Vec<String>,
+
+    /// Hash index of the references, read from FILE, or built and written
+    /// to it when missing or made for other references [default:
+    /// REFERENCE.idx for a single reference file, kept in memory otherwise]
+    #[arg(long, value_name = "FILE")]
+    pub index: Option<String>,
+
+    /// Output distances (TSV) to FILE [default: stdout]
+    #[arg(short, value_name = "FILE")]
+    pub output: Option<String>,
+
+    /// Number of nearest references reported per query
+    #[arg(short = 'n', long, default_value_t = 10, value_name = "INT")]
+    pub top: usize,
+
+    /// Number of references sharing the most hashes with a query that are
+    /// scored to find its nearest ones
+    #[arg(long, default_value_t = DEFAULT_CANDIDATES, value_name = "INT")]
+    pub candidates: usize,
+
+    /// Amount of extra sketching before filtering, for query sequences
+    #[arg(short = 'x', long, default_value_t = 200, value_name = "INT")]
+    pub oversketch: usize,
+
+    /// Number of threads to use
+    #[arg(short, default_value_t = 1, value_name = "INT")]
+    pub threads: usize,
+
+    #[command(flatten)]
+    pub log: LogArgs,
 }
@@ -0,0 +1,264 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::io::Write;
+
+use speedytree::DistanceMatrix;
+
+/// Linkage criterion of the hierarchical clustering
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Linkage {
+    /// Distance between the closest members
+    Single,
+    /// Distance between the farthest members
+    Complete,
+    /// Mean distance between members (UPGMA)
+    #[default]
+    Average,
+}
+
+/// Flat clusters obtained by cutting a dendrogram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Clustering {
+    /// Cluster of each genome, in matrix order
+    pub assignments: Vec<usize>,
+    /// Representative genome (medoid) of each cluster
+    pub representatives: Vec<usize>,
+}
+
+impl Clustering {
+    pub fn n_clusters(&self) -> usize {
+        self.representatives.len()
+    }
+
+    /// Genome indices of each cluster
+    pub fn members(&self) -> Vec<Vec<usize>> {
+        let mut members = vec![Vec::new(); self.n_clusters()];
+        for (genome, &cluster) in self.assignments.iter().enumerate() {
+            members[cluster].push(genome);
+        }
+        members
+    }
+}
+
+/// Convert an ANI percentage (e.g. 95) to a Mash distance threshold
+pub fn ani_to_distance(ani: f64) -> f64 {
+    1.0 - ani / 100.0
+}
+
+/// Merges of an agglomerative clustering as `(a, b, height)`.
+///
+/// Uses the nearest-neighbor chain algorithm with Lance-Williams updates,
+/// which needs O(n²) time and memory. All three linkages are reducible, so
+/// the resulting dendrogram is monotone.
+pub fn dendrogram(matrix: &DistanceMatrix, linkage: Linkage) -> Vec<(usize, usize, f64)> {
+    let n = matrix.size();
+    let mut dist = matrix.matrix.clone();
+    let mut size = vec![1usize; n];
+    let mut active = vec![true; n];
+    let mut merges = Vec::with_capacity(n.saturating_sub(1));
+    let mut chain: Vec<usize> = Vec::new();
+
+    for _ in 1..n {
+        if chain.is_empty() {
+            chain.push(active.iter().position(|&a| a).unwrap());
+        }
+        loop {
+            let a = *chain.last().unwrap();
+            let previous = chain.len().checked_sub(2).map(|i| chain[i]);
+            // Nearest neighbour of a, preferring the previous chain element on ties
+            let mut best = previous;
+            let mut best_dist = previous.map_or(f64::INFINITY, |p| dist[a][p]);
+            for c in (0..n).filter(|&c| active[c] && c != a) {
+                if dist[a][c] < best_dist {
+                    best = Some(c);
+                    best_dist = dist[a][c];
+                }
+            }
+            let b = best.expect("at least two active clusters");
+            if Some(b) == previous {
+                chain.truncate(chain.len() - 2);
+                merges.push((a.min(b), a.max(b), best_dist));
+                merge(&mut dist, &mut size, &mut active, a, b, linkage);
+                break;
+            }
+            chain.push(b);
+        }
+    }
+
+    merges
+}
+
+/// Merge cluster `b` into `a` and update distances to the other clusters
+fn merge(
+    dist: &mut [Vec<f64>],
+    size: &mut [usize],
+    active: &mut [bool],
+    a: usize,
+    b: usize,
+    linkage: Linkage,
+) {
+    let (na, nb) = (size[a] as f64, size[b] as f64);
+    for c in 0..dist.len() {
+        if !active[c] || c == a || c == b {
+            continue;
+        }
+        let d = match linkage {
+            Linkage::Single => dist[a][c].min(dist[b][c]),
+            Linkage::Complete => dist[a][c].max(dist[b][c]),
+            Linkage::Average => (na * dist[a][c] + nb * dist[b][c]) / (na + nb),
+        };
+        dist[a][c] = d;
+        dist[c][a] = d;
+    }
+    size[a] += size[b];
+    active[b] = false;
+}
+
+/// Cluster genomes so that clusters are merged while their linkage distance
+/// is at most `threshold`
+pub fn cluster(matrix: &DistanceMatrix, linkage: Linkage, threshold: f64) -> Clustering {
+    let n = matrix.size();
+    let mut parent: Vec<usize> = (0..n).collect();
+    fn find(parent: &mut [usize], mut x: usize) -> usize {
+        while parent[x] != x {
+            parent[x] = parent[parent[x]];
+            x = parent[x];
+        }
+        x
+    }
+
+    for (a, b, height) in dendrogram(matrix, linkage) {
+        if height <= threshold {
+            let (ra, rb) = (find(&mut parent, a), find(&mut parent, b));
+            parent[ra.max(rb)] = ra.min(rb);
+        }
+    }
+
+    // Number clusters by their first genome
+    let mut ids = vec![usize::MAX; n];
+    let mut assignments = Vec::with_capacity(n);
+    let mut n_clusters = 0;
+    for genome in 0..n {
+        let root = find(&mut parent, genome);
+        if ids[root] == usize::MAX {
+            ids[root] = n_clusters;
+            n_clusters += 1;
+        }
+        assignments.push(ids[root]);
+    }
+
+    let mut members = vec![Vec::new(); n_clusters];
+    for (genome, &cluster) in assignments.iter().enumerate() {
+        members[cluster].push(genome);
+    }
+    let representatives = members.iter().map(|m| medoid(matrix, m)).collect();
+
+    Clustering {
+        assignments,
+        representatives,
+    }
+}
+
+/// Member with the smallest sum of distances to the other members
+pub fn medoid(matrix: &DistanceMatrix, members: &[usize]) -> usize {
+    members
+        .iter()
+        .copied()
+        .min_by(|&a, &b| {
+            let sa: f64 = members.iter().map(|&m| matrix.matrix[a][m]).sum();
+            let sb: f64 = members.iter().map(|&m| matrix.matrix[b][m]).sum();
+            sa.total_cmp(&sb)
+        })
+        .expect("clusters are not empty")
+}
+
+/// Write genome-to-cluster assignments as TSV
+pub fn write_clusters<W: Write>(
+    out: &mut W,
+    matrix: &DistanceMatrix,
+    clustering: &Clustering,
+) -> anyhow::Result<()> {
+    writeln!(out, "genome\tcluster\trepresentative")?;
+    for (genome, &cluster) in clustering.assignments.iter().enumerate() {
+        writeln!(
+            out,
+            "{}\t{}\t{}",
+            matrix.names[genome],
+            cluster + 1,
+            matrix.names[clustering.representatives[cluster]]
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> DistanceMatrix {
+        DistanceMatrix {
+            matrix: vec![
+                vec![0.0, 0.01, 0.04, 0.20, 0.22],
+                vec![0.01, 0.0, 0.03, 0.21, 0.23],
+                vec![0.04, 0.03, 0.0, 0.22, 0.24],
+                vec![0.20, 0.21, 0.22, 0.0, 0.02],
+                vec![0.22, 0.23, 0.24, 0.02, 0.0],
+            ],
+            names: ["a", "b", "c", "d", "e"].map(String::from).to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_dendrogram() {
+        let merges = dendrogram(&example(), Linkage::Single);
+        assert_eq!(merges.len(), 4);
+        let mut heights: Vec<f64> = merges.iter().map(|m| m.2).collect();
+        heights.sort_by(f64::total_cmp);
+        assert_eq!(heights, vec![0.01, 0.02, 0.03, 0.20]);
+
+        let merges = dendrogram(&example(), Linkage::Complete);
+        let mut heights: Vec<f64> = merges.iter().map(|m| m.2).collect();
+        heights.sort_by(f64::total_cmp);
+        assert_eq!(heights, vec![0.01, 0.02, 0.04, 0.24]);
+    }
+
+    #[test]
+    fn test_cluster_linkages() {
+        let matrix = example();
+
+        let single = cluster(&matrix, Linkage::Single, 0.03);
+        assert_eq!(single.assignments, vec![0, 0, 0, 1, 1]);
+        assert_eq!(single.representatives, vec![1, 3]);
+
+        // c is 0.04 away from a, too far for complete linkage
+        let complete = cluster(&matrix, Linkage::Complete, 0.03);
+        assert_eq!(complete.assignments, vec![0, 0, 1, 2, 2]);
+
+        let average = cluster(&matrix, Linkage::Average, 0.035);
+        assert_eq!(average.assignments, vec![0, 0, 0, 1, 1]);
+
+        let singletons = cluster(&matrix, Linkage::Average, 0.0);
+        assert_eq!(singletons.n_clusters(), 5);
+    }
+
+    #[test]
+    fn test_ani_to_distance() {
+        assert!((ani_to_distance(95.0) - 0.05).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_write_clusters() {
+        let matrix = example();
+        let clustering = cluster(&matrix, Linkage::Single, 0.03);
+        let mut out = Vec::new();
+        write_clusters(&mut out, &matrix, &clustering).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(
+            text,
+            "genome\tcluster\trepresentative\na\t1\tb\nb\t1\tb\nc\t1\tb\nd\t2\td\ne\t2\td\n"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::cluster::Clustering;
+use crate::error::Error;
+use crate::utils::AssemblyStats;
+
+/// Assembly statistic used to pick the representative of a group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Criterion {
+    /// Highest N50
+    #[default]
+    N50,
+    /// Largest genome
+    Size,
+    /// Lowest fraction of N bases
+    Ns,
+}
+
+/// Compare two assemblies, the greater one being the better representative.
+///
+/// Ties on the main criterion are broken by the other statistics.
+pub fn compare_assemblies(a: &AssemblyStats, b: &AssemblyStats, criterion: Criterion) -> Ordering {
+    let n50 = a.n50.cmp(&b.n50);
+    let size = a.length.cmp(&b.length);
+    // Fewer Ns is better
+    let ns = b.n_fraction().total_cmp(&a.n_fraction());
+    match criterion {
+        Criterion::N50 => n50.then(ns).then(size),
+        Criterion::Size => size.then(n50).then(ns),
+        Criterion::Ns => ns.then(n50).then(size),
+    }
+}
+
+/// Read a quality TSV with a header line, genome names in the first column
+/// and scores (higher is better) in the second one
+pub fn read_quality(path: &str) -> anyhow::Result<HashMap<String, f64>> {
+    let file = File::open(path).map_err(|e| Error::io(path, e))?;
+    let mut scores = HashMap::new();
+
+    for line in BufReader::new(file).lines().skip(1) {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut fields = line.split('\t');
+        let (Some(name), Some(score)) = (fields.next(), fields.next()) else {
+            return Err(
+                Error::Input(format!("Malformed quality line in {}: {}", path, line)).into(),
+            );
+        };
+        let score: f64 = score
+            .trim()
+            .parse()
+            .map_err(|_| Error::Input(format!("Invalid quality score in {}: {}", path, line)))?;
+        scores.insert(name.trim().to_string(), score);
+    }
+
+    Ok(scores)
+}
+
+/// Pick the best genome of each cluster.
+///
+/// Genomes are ranked by their quality score when `quality` is given (genomes
+/// without a score come last), then by assembly statistics. Remaining ties
+/// keep the genome that comes first in `names`.
+pub fn select_representatives(
+    clustering: &Clustering,
+    names: &[String],
+    stats: &HashMap<String, AssemblyStats>,
+    quality: Option<&HashMap<String, f64>>,
+    criterion: Criterion,
+) -> Vec<usize> {
+    let default_stats = AssemblyStats::default();
+    let compare = |a: usize, b: usize| {
+        let by_quality = match quality {
+            Some(quality) => {
+                let qa = quality.get(&names[a]).copied().unwrap_or(f64::NEG_INFINITY);
+                let qb = quality.get(&names[b]).copied().unwrap_or(f64::NEG_INFINITY);
+                qa.total_cmp(&qb)
+            }
+            None => Ordering::Equal,
+        };
+        let sa = stats.get(&names[a]).unwrap_or(&default_stats);
+        let sb = stats.get(&names[b]).unwrap_or(&default_stats);
+        by_quality
+            .then_with(|| compare_assemblies(sa, sb, criterion))
+            .then(b.cmp(&a))
+    };
+
+    clustering
+        .members()
+        .iter()
+        .map(|members| {
+            members
+                .iter()
+                .copied()
+                .max_by(|&a, &b| compare(a, b))
+                .expect("clusters are not empty")
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn assembly(length: usize, n50: usize, n_count: usize) -> AssemblyStats {
+        AssemblyStats {
+            length,
+            records: 1,
+            n50,
+            n_count,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_select_representatives() {
+        let names: Vec<String> = ["a", "b", "c", "d"].map(String::from).to_vec();
+        let stats = HashMap::from([
+            ("a".to_string(), assembly(4_000_000, 50_000, 0)),
+            ("b".to_string(), assembly(4_200_000, 300_000, 40_000)),
+            ("c".to_string(), assembly(4_100_000, 20_000, 0)),
+            ("d".to_string(), assembly(3_000_000, 3_000_000, 0)),
+        ]);
+        let clustering = Clustering {
+            assignments: vec![0, 0, 0, 1],
+            representatives: vec![0, 3],
+        };
+
+        let reps = select_representatives(&clustering, &names, &stats, None, Criterion::N50);
+        assert_eq!(reps, vec![1, 3]);
+        let reps = select_representatives(&clustering, &names, &stats, None, Criterion::Size);
+        assert_eq!(reps, vec![1, 3]);
+        let reps = select_representatives(&clustering, &names, &stats, None, Criterion::Ns);
+        assert_eq!(reps, vec![0, 3]);
+
+        let quality = HashMap::from([("c".to_string(), 90.0), ("a".to_string(), 80.0)]);
+        let reps =
+            select_representatives(&clustering, &names, &stats, Some(&quality), Criterion::N50);
+        assert_eq!(reps, vec![2, 3]);
+    }
+
+    #[test]
+    fn test_read_quality() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "genome\tscore").unwrap();
+        writeln!(file, "a\t95.5").unwrap();
+        writeln!(file, "b\t80").unwrap();
+        let scores = read_quality(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(scores["a"], 95.5);
+        assert_eq!(scores.len(), 2);
+
+        writeln!(file, "c\tbad").unwrap();
+        assert!(read_quality(file.path().to_str().unwrap()).is_err());
+    }
+}
@@ -15,20 +15,115 @@ use finch::{
     serialization::{Sketch, SketchDistance},
 };
 use itertools::Itertools;
+use rayon::prelude::*;
 use speedytree::DistanceMatrix;
 
+use crate::progress;
+
 /// Compute distance between sketches
 pub fn compute_distances(sketches: Vec<Sketch>) -> Vec<SketchDistance> {
-    sketches
+    let n = sketches.len() as u64;
+    let bar = progress::bar(n * (n + 1) / 2, "Distances");
+    let distances = sketches
         .into_iter()
         .combinations_with_replacement(2)
         .filter_map(|pair| {
+            bar.inc(1);
             let dist = distance(&pair[0], &pair[1], false).ok()?;
             (dist.mash_distance <= 1.0).then_some(dist)
         })
+        .collect();
+    bar.finish_and_clear();
+    distances
+}
+
+/// Compute distances between every query and every reference sketch
+pub fn compute_query_distances(queries: &[Sketch], references: &[Sketch]) -> Vec<SketchDistance> {
+    queries
+        .iter()
+        .cartesian_product(references)
+        .filter_map(|(query, reference)| distance(query, reference, false).ok())
         .collect()
 }
 
+/// Distances from `query` to the references within `max_distance`, closest
+/// first and limited to the `top` closest references
+pub fn query_hits(
+    query: &Sketch,
+    references: &[Sketch],
+    max_distance: Option<f64>,
+    top: Option<usize>,
+) -> Vec<SketchDistance> {
+    let mut hits: Vec<SketchDistance> = references
+        .par_iter()
+        .filter_map(|reference| distance(query, reference, false).ok())
+        .filter(|d| !max_distance.is_some_and(|max| d.mash_distance > max))
+        .collect();
+    sort_hits(&mut hits);
+    if let Some(top) = top {
+        hits.truncate(top);
+    }
+    hits
+}
+
+/// Sort distances closest first, ties by reference name
+pub fn sort_hits(hits: &mut [SketchDistance]) {
+    hits.sort_by(|a, b| {
+        a.mash_distance
+            .total_cmp(&b.mash_distance)
+            .then_with(|| a.reference.cmp(&b.reference))
+    });
+}
+
+/// Write the header of a distance TSV
+pub fn write_hits_header<W: Write>(out: &mut W) -> anyhow::Result<()> {
+    writeln!(
+        out,
+        "query\treference\tdistance\tjaccard\tcontainment\tshared_hashes"
+    )?;
+    Ok(())
+}
+
+/// Write distances as TSV rows, shared hashes given as `common/total`;
+/// genomes are named by their label if they have one
+pub fn write_hits<W: Write>(
+    out: &mut W,
+    hits: &[SketchDistance],
+    labels: &HashMap<String, String>,
+) -> anyhow::Result<()> {
+    let name = |path: &str| {
+        let name = sketch_name(path);
+        labels.get(&name).cloned().unwrap_or(name)
+    };
+    for hit in hits {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}/{}",
+            name(&hit.query),
+            name(&hit.reference),
+            hit.mash_distance,
+            hit.jaccard,
+            hit.containment,
+            hit.common_hashes,
+            hit.total_hashes
+        )?;
+    }
+    Ok(())
+}
+
+/// Genome name of a sketch: the file stem of the sequence file it comes
+/// from, or `stdin`
+pub fn sketch_name(path: &str) -> String {
+    if path == crate::inputs::STDIN {
+        return "stdin".to_string();
+    }
+    Path::new(path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_string()
+}
+
 /// Computes a distance matrice from a list of sketches distances
 pub fn distance_to_matrix(distances: Vec<SketchDistance>) -> DistanceMatrix {
     // SketchDistance contains more data than needed for this task like
@@ -38,21 +133,19 @@ pub fn distance_to_matrix(distances: Vec<SketchDistance>) -> DistanceMatrix {
     let mut map: HashMap<(String, String), f64> = HashMap::new();
 
     for distance in distances {
-        let query_basename = Path::new(&distance.query)
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("")
-            .to_string();
-        let ref_basename = Path::new(&distance.reference)
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("")
-            .to_string();
+        let query_basename = sketch_name(&distance.query);
+        let ref_basename = sketch_name(&distance.reference);
         map.insert((query_basename, ref_basename), distance.mash_distance);
     }
 
-    // Collect unique names
-    let unique_names: Vec<String> = map.keys().map(|(q, _)| q.clone()).unique().collect();
+    // Collect unique names, sorted so that matrices do not depend on the
+    // order of the hash map
+    let unique_names: Vec<String> = map
+        .keys()
+        .map(|(q, _)| q.clone())
+        .unique()
+        .sorted()
+        .collect();
     let n = unique_names.len();
 
     // Initialize N x N matrix with zeros
@@ -77,6 +170,26 @@ pub fn distance_to_matrix(distances: Vec<SketchDistance>) -> DistanceMatrix {
     }
 }
 
+/// Rename the genomes of a matrix that have a label
+pub fn relabel(dist: &mut DistanceMatrix, labels: &HashMap<String, String>) {
+    for name in &mut dist.names {
+        if let Some(label) = labels.get(name) {
+            *name = label.clone();
+        }
+    }
+}
+
+/// Restrict a distance matrix to the given rows and columns
+pub fn submatrix(dist: &DistanceMatrix, indices: &[usize]) -> DistanceMatrix {
+    DistanceMatrix {
+        matrix: indices
+            .iter()
+            .map(|&i| indices.iter().map(|&j| dist.matrix[i][j]).collect())
+            .collect(),
+        names: indices.iter().map(|&i| dist.names[i].clone()).collect(),
+    }
+}
+
 /// Write a PHYLIP file from a distance matrice
 pub fn to_phylip(dist: DistanceMatrix, output: &str) -> anyhow::Result<()> {
     let mut file = fs::OpenOptions::new()
@@ -84,15 +197,58 @@ pub fn to_phylip(dist: DistanceMatrix, output: &str) -> anyhow::Result<()> {
         .append(true)
         .open(PathBuf::from(output).join("distance.phylip"))?;
 
-    writeln!(file, "{}", dist.names.len())?;
+    write_phylip(&mut file, &dist)
+}
+
+/// Write a distance matrice in PHYLIP format
+pub fn write_phylip<W: Write>(out: &mut W, dist: &DistanceMatrix) -> anyhow::Result<()> {
+    writeln!(out, "{}", dist.names.len())?;
 
     for (name, row) in dist.names.iter().zip(&dist.matrix) {
-        writeln!(file, "{} {}", name, row.iter().format(" "))?;
+        writeln!(out, "{} {}", name, row.iter().format(" "))?;
     }
 
     Ok(())
 }
 
+/// Write the distance of every pair of genomes at each k-mer size as TSV,
+/// one column per matrix
+pub fn write_distances_per_k<W: Write>(
+    out: &mut W,
+    matrices: &[(u8, DistanceMatrix)],
+) -> anyhow::Result<()> {
+    let Some((_, first)) = matrices.first() else {
+        return Ok(());
+    };
+    let positions: Vec<HashMap<&str, usize>> = matrices
+        .iter()
+        .map(|(_, m)| {
+            m.names
+                .iter()
+                .enumerate()
+                .map(|(i, n)| (n.as_str(), i))
+                .collect()
+        })
+        .collect();
+
+    writeln!(
+        out,
+        "genome1\tgenome2\t{}",
+        matrices.iter().map(|(k, _)| format!("k{}", k)).format("\t")
+    )?;
+    for (a, b) in (0..first.names.len()).tuple_combinations() {
+        let (name_a, name_b) = (first.names[a].as_str(), first.names[b].as_str());
+        let distances = matrices.iter().zip(&positions).map(|((_, m), pos)| {
+            match (pos.get(name_a), pos.get(name_b)) {
+                (Some(&i), Some(&j)) => m.matrix[i][j].to_string(),
+                _ => "NA".to_string(),
+            }
+        });
+        writeln!(out, "{}\t{}\t{}", name_a, name_b, distances.format("\t"))?;
+    }
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -116,6 +272,47 @@ mod tests {
             assert!(distance.mash_distance <= 1.0);
         }
     }
+    #[test]
+    fn test_compute_query_distances() {
+        let query = finch::open_sketch_file("test/sketches/bacam.fna.msh").unwrap();
+        let references = read_test_sketches();
+        let distances = compute_query_distances(&query, &references);
+        assert_eq!(distances.len(), 2);
+        let itself = distances.iter().find(|d| d.reference == d.query).unwrap();
+        assert_eq!(itself.mash_distance, 0.0);
+    }
+
+    #[test]
+    fn test_query_hits() {
+        let query = finch::open_sketch_file("test/sketches/bacam.fna.msh").unwrap();
+        let references = read_test_sketches();
+
+        let hits = query_hits(&query[0], &references, None, None);
+        assert_eq!(hits.len(), 2);
+        assert_eq!(hits[0].mash_distance, 0.0);
+        assert!(hits[0].mash_distance <= hits[1].mash_distance);
+
+        assert_eq!(query_hits(&query[0], &references, None, Some(1)).len(), 1);
+        assert_eq!(query_hits(&query[0], &references, Some(0.0), None).len(), 1);
+
+        let mut out = Vec::new();
+        write_hits_header(&mut out).unwrap();
+        write_hits(&mut out, &hits[..1], &HashMap::new()).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        assert_eq!(
+            text.lines().nth(1).unwrap().split('\t').next(),
+            Some("bacam")
+        );
+    }
+
+    fn read_test_sketches() -> Vec<Sketch> {
+        let mut sketches = Vec::new();
+        for file in fs::read_dir("test/sketches").unwrap() {
+            sketches.push(finch::open_sketch_file(file.unwrap().path()).unwrap());
+        }
+        sketches.into_iter().flatten().collect()
+    }
+
     // Test distance_to_matrix function
     #[test]
     fn test_distance_to_matrix() {
@@ -125,14 +322,56 @@ mod tests {
         }
         let data = sketches.into_iter().flatten().collect_vec();
 
-        let distances = compute_distances(data);
+        let distances = compute_distances(data.clone());
 
         let matrix = distance_to_matrix(distances);
 
         // Assert that the matrix is computed correctly
         assert_eq!(matrix.matrix.len(), 2);
         assert_eq!(matrix.matrix[0].len(), 2);
-        assert_eq!(matrix.names.len(), 2);
+        assert_eq!(matrix.names, vec!["bacam", "bacsp"]);
+
+        // Names are sorted whatever the order of the sketches
+        let reversed = distance_to_matrix(compute_distances(data.into_iter().rev().collect()));
+        assert_eq!(reversed.names, matrix.names);
+        assert_eq!(reversed.matrix, matrix.matrix);
+    }
+
+    #[test]
+    fn test_submatrix() {
+        let dist = DistanceMatrix {
+            matrix: vec![
+                vec![0.0, 0.5, 0.8],
+                vec![0.5, 0.0, 0.9],
+                vec![0.8, 0.9, 0.0],
+            ],
+            names: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+        };
+        let sub = submatrix(&dist, &[2, 0]);
+        assert_eq!(sub.names, vec!["c", "a"]);
+
+        let mut labelled = sub.clone();
+        relabel(
+            &mut labelled,
+            &HashMap::from([("a".to_string(), "A x".to_string())]),
+        );
+        assert_eq!(labelled.names, vec!["c", "A x"]);
+        assert_eq!(sub.matrix, vec![vec![0.0, 0.8], vec![0.8, 0.0]]);
+    }
+
+    #[test]
+    fn test_write_distances_per_k() {
+        let m = |names: [&str; 3], d: f64| DistanceMatrix {
+            matrix: vec![vec![0.0, d, 0.5], vec![d, 0.0, 0.25], vec![0.5, 0.25, 0.0]],
+            names: names.map(String::from).to_vec(),
+        };
+        let matrices = vec![(15, m(["a", "b", "c"], 0.1)), (21, m(["b", "a", "c"], 0.2))];
+        let mut out = Vec::new();
+        write_distances_per_k(&mut out, &matrices).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "genome1\tgenome2\tk15\tk21\na\tb\t0.1\t0.2\na\tc\t0.5\t0.25\nb\tc\t0.25\t0.5\n"
+        );
     }
 
     // Test to_phylip function
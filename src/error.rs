@@ -0,0 +1,126 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::io::{self, Write};
+
+/// Failures of a run, grouped by the step that failed.
+///
+/// Library functions return [`anyhow::Result`] with one of these at the
+/// root of the error chain, so that callers can tell failures apart with
+/// [`exit_code`] or by downcasting.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Missing, unreadable as sequences or otherwise unusable inputs
+    #[error("Invalid input: {0}")]
+    Input(String),
+    /// Sequences could not be sketched, or sketch files could not be read
+    #[error("Sketching failed: {0}")]
+    Sketch(String),
+    /// Distances could not be computed or read
+    #[error("Distance computation failed: {0}")]
+    Distance(String),
+    /// A tree could not be built, read or compared
+    #[error("Tree error: {0}")]
+    Tree(String),
+    /// Reading or writing a file failed
+    #[error("Could not access {path}")]
+    Io {
+        path: String,
+        #[source]
+        source: io::Error,
+    },
+}
+
+impl Error {
+    pub fn io(path: impl Into<String>, source: io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+
+    /// Process exit code of this error.
+    ///
+    /// | code | meaning                                |
+    /// |------|----------------------------------------|
+    /// | 0    | success                                |
+    /// | 1    | unexpected error                       |
+    /// | 2    | invalid command line (reported by clap)|
+    /// | 3    | invalid input                          |
+    /// | 4    | IO error                               |
+    /// | 5    | sketching error                        |
+    /// | 6    | distance error                         |
+    /// | 7    | tree error                             |
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::Input(_) => 3,
+            Error::Io { .. } => 4,
+            Error::Sketch(_) => 5,
+            Error::Distance(_) => 6,
+            Error::Tree(_) => 7,
+        }
+    }
+}
+
+/// Input left out of a run because it could not be used
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Failure {
+    pub input: String,
+    pub reason: String,
+}
+
+/// Write failures as a TSV table
+pub fn write_failures(out: &mut dyn Write, failures: &[Failure]) -> io::Result<()> {
+    writeln!(out, "genome\treason")?;
+    for failure in failures {
+        writeln!(out, "{}\t{}", failure.input, failure.reason)?;
+    }
+    Ok(())
+}
+
+/// Exit code of the first [`Error`] in the chain of `err`; other IO errors
+/// map to the IO code and anything else to 1
+pub fn exit_code(err: &anyhow::Error) -> i32 {
+    err.chain()
+        .find_map(|cause| {
+            if let Some(e) = cause.downcast_ref::<Error>() {
+                Some(e.exit_code())
+            } else {
+                cause.downcast_ref::<io::Error>().map(|_| 4)
+            }
+        })
+        .unwrap_or(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_exit_code() {
+        let err = anyhow::Error::from(Error::Tree("no tree".to_string()));
+        assert_eq!(exit_code(&err), 7);
+        let err = Err::<(), _>(Error::Input("two genomes".to_string()))
+            .context("While building the tree")
+            .unwrap_err();
+        assert_eq!(exit_code(&err), 3);
+        assert_eq!(err.root_cause().to_string(), "Invalid input: two genomes");
+        let err = anyhow::Error::from(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(exit_code(&err), 4);
+        assert_eq!(exit_code(&anyhow::anyhow!("other")), 1);
+    }
+
+    #[test]
+    fn test_write_failures() {
+        let failures = [Failure {
+            input: "a.fna".to_string(),
+            reason: "not a FASTA file".to_string(),
+        }];
+        let mut out = Vec::new();
+        write_failures(&mut out, &failures).unwrap();
+        assert_eq!(out, b"genome\treason\na.fna\tnot a FASTA file\n");
+    }
+}
@@ -0,0 +1,201 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::collections::HashSet;
+use std::io::Write;
+
+/// Length of the windows scored for low complexity
+const DUST_WINDOW: usize = 64;
+
+/// DUST score above which a window is masked, as in DustMasker
+const DUST_THRESHOLD: f64 = 20.0;
+
+/// Filters applied to each record of a genome while it is read, before
+/// sketching
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RecordFilter {
+    /// Records (contigs) shorter than this are dropped
+    pub min_length: Option<usize>,
+    /// Replace low-complexity regions by N before sketching
+    pub mask_low_complexity: bool,
+}
+
+impl RecordFilter {
+    /// Whether a record of `length` bases is kept
+    pub fn keeps(&self, length: usize) -> bool {
+        match self.min_length {
+            Some(min) => length >= min,
+            None => true,
+        }
+    }
+}
+
+/// What the record filters removed from one genome
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecordCounts {
+    pub dropped_records: usize,
+    pub dropped_bases: usize,
+    pub masked_bases: usize,
+}
+
+/// Outcome of the filters for one input genome
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterDecision {
+    pub input: String,
+    pub records: RecordCounts,
+    /// Why the genome is left out of the analysis, `None` if it is kept
+    pub excluded: Option<String>,
+}
+
+/// DUST score of a window: how much its triplets repeat, from 0 for a
+/// sequence without repeated triplet to about 30 for a homopolymer
+fn dust_score(window: &[u8]) -> f64 {
+    let mut counts = [0u32; 64];
+    let mut triplets = 0;
+    for triplet in window.windows(3) {
+        let index = triplet.iter().try_fold(0, |index, &base| {
+            let code = match base.to_ascii_uppercase() {
+                b'A' => 0,
+                b'C' => 1,
+                b'G' => 2,
+                b'T' => 3,
+                _ => return None,
+            };
+            Some(index * 4 + code)
+        });
+        if let Some(index) = index {
+            counts[index] += 1;
+            triplets += 1;
+        }
+    }
+    if triplets < 2 {
+        return 0.0;
+    }
+    let repeats: u32 = counts.iter().map(|&c| c * c.saturating_sub(1) / 2).sum();
+    f64::from(repeats) / f64::from(triplets - 1)
+}
+
+/// Replace the low-complexity windows of `seq` by N, scanning windows of
+/// 64 bases overlapping by half; returns the number of newly masked bases
+pub fn mask_low_complexity(seq: &mut [u8]) -> usize {
+    if seq.len() < DUST_WINDOW {
+        return 0;
+    }
+    let mut masked = vec![false; seq.len()];
+    let last = seq.len() - DUST_WINDOW;
+    let mut starts: Vec<usize> = (0..=last).step_by(DUST_WINDOW / 2).collect();
+    if starts.last() != Some(&last) {
+        starts.push(last);
+    }
+    for start in starts {
+        let window = start..start + DUST_WINDOW;
+        if dust_score(&seq[window.clone()]) > DUST_THRESHOLD {
+            masked[window].iter_mut().for_each(|m| *m = true);
+        }
+    }
+
+    let mut count = 0;
+    for (base, masked) in seq.iter_mut().zip(masked) {
+        if masked && !base.eq_ignore_ascii_case(&b'N') {
+            *base = b'N';
+            count += 1;
+        }
+    }
+    count
+}
+
+/// Write the filter decision of each genome as a TSV table, flagging the
+/// inputs in `outliers` as genome size outliers
+pub fn write_report(
+    out: &mut dyn Write,
+    decisions: &[FilterDecision],
+    outliers: &HashSet<&str>,
+) -> anyhow::Result<()> {
+    writeln!(
+        out,
+        "genome\tstatus\tdropped_contigs\tdropped_bases\tmasked_bases\toutlier\treason"
+    )?;
+    for decision in decisions {
+        let records = &decision.records;
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            decision.input,
+            if decision.excluded.is_some() {
+                "excluded"
+            } else {
+                "kept"
+            },
+            records.dropped_records,
+            records.dropped_bases,
+            records.masked_bases,
+            if outliers.contains(decision.input.as_str()) {
+                "yes"
+            } else {
+                "no"
+            },
+            decision.excluded.as_deref().unwrap_or("")
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dust_score() {
+        assert!(dust_score(&[b'A'; 64]) > DUST_THRESHOLD);
+        let random = b"ATGCGTACGTTAGCCTAGGCTAACGTTGCAGTCCGATGACTTAGCAGTGCATCGATCGGATCAA";
+        assert!(dust_score(random) < 2.0);
+        assert_eq!(dust_score(b"NNNNNNNN"), 0.0);
+    }
+
+    #[test]
+    fn test_mask_low_complexity() {
+        let unique = b"ATGCGTACGTTAGCCTAGGCTAACGTTGCAGTCCGATGACTTAGCAGTGCATCGATCGGATCAA";
+        let mut seq = [&unique[..], &[b'T'; 100], &unique[..]].concat();
+        let masked = mask_low_complexity(&mut seq);
+        // Windows fully inside the poly-T run are masked, mixed ones are not
+        assert_eq!(masked, 96);
+        assert!(seq[64..160].iter().all(|&b| b == b'N'));
+        assert!(!seq[..64].contains(&b'N'));
+        assert!(!seq[164..].contains(&b'N'));
+
+        let mut short = b"AAAAAAAAAA".to_vec();
+        assert_eq!(mask_low_complexity(&mut short), 0);
+    }
+
+    #[test]
+    fn test_write_report() {
+        let decisions = [
+            FilterDecision {
+                input: "a.fna".to_string(),
+                records: RecordCounts {
+                    dropped_records: 2,
+                    dropped_bases: 300,
+                    masked_bases: 64,
+                },
+                excluded: None,
+            },
+            FilterDecision {
+                input: "b.fna".to_string(),
+                excluded: Some("N fraction 0.2 is above 0.1".to_string()),
+                ..Default::default()
+            },
+        ];
+        let mut out = Vec::new();
+        write_report(&mut out, &decisions, &HashSet::from(["a.fna"])).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[0].split('\t').nth(5), Some("outlier"));
+        assert_eq!(lines[1], "a.fna\tkept\t2\t300\t64\tyes\t");
+        assert_eq!(
+            lines[2],
+            "b.fna\texcluded\t0\t0\t0\tno\tN fraction 0.2 is above 0.1"
+        );
+    }
+}
@@ -0,0 +1,321 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use itertools::Itertools;
+use speedytree::DistanceMatrix;
+
+use crate::tree::{format_length, quote_label, NewickOptions, NodeId, Tree};
+
+/// Output format of the tree
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TreeFormat {
+    #[default]
+    Newick,
+    /// Nexus with TAXA, DISTANCES and TREES blocks
+    Nexus,
+    Phyloxml,
+    Nexml,
+}
+
+/// Genome size of each leaf, indexed by leaf label
+pub type GenomeSizes = HashMap<String, usize>;
+
+/// Write `tree` in the requested format.
+///
+/// The distance matrix is only used by the Nexus writer. Genome sizes are
+/// added as leaf annotations when available.
+pub fn write_tree(
+    format: TreeFormat,
+    tree: &Tree,
+    matrix: Option<&DistanceMatrix>,
+    sizes: &GenomeSizes,
+    options: &NewickOptions,
+) -> String {
+    match format {
+        TreeFormat::Newick => annotated(tree, sizes).to_newick(options),
+        TreeFormat::Nexus => to_nexus(tree, matrix, sizes, options),
+        TreeFormat::Phyloxml => to_phyloxml(tree, sizes, options),
+        TreeFormat::Nexml => to_nexml(tree, sizes, options),
+    }
+}
+
+/// Copy of `tree` with genome sizes stored as `[&genome_size=N]` leaf comments
+fn annotated(tree: &Tree, sizes: &GenomeSizes) -> Tree {
+    let mut tree = tree.clone();
+    for id in tree.leaves() {
+        let node = tree.node_mut(id);
+        if let Some(size) = node.label.as_ref().and_then(|l| sizes.get(l)) {
+            node.comment = Some(format!("&genome_size={}", size));
+        }
+    }
+    tree
+}
+
+/// Nexus file with TAXA, DISTANCES (when a matrix is given) and TREES blocks
+pub fn to_nexus(
+    tree: &Tree,
+    matrix: Option<&DistanceMatrix>,
+    sizes: &GenomeSizes,
+    options: &NewickOptions,
+) -> String {
+    let labels = tree.leaf_labels();
+    let mut out = String::from("#NEXUS\n\n");
+
+    out.push_str("BEGIN TAXA;\n");
+    let _ = writeln!(out, "\tDIMENSIONS NTAX={};", labels.len());
+    out.push_str("\tTAXLABELS\n");
+    for label in &labels {
+        let _ = writeln!(out, "\t\t{}", quote_label(label, options.quoting));
+    }
+    out.push_str("\t;\nEND;\n\n");
+
+    if let Some(matrix) = matrix {
+        out.push_str("BEGIN DISTANCES;\n");
+        let _ = writeln!(out, "\tDIMENSIONS NTAX={};", matrix.names.len());
+        out.push_str("\tFORMAT TRIANGLE=BOTH DIAGONAL LABELS;\n");
+        out.push_str("\tMATRIX\n");
+        for (name, row) in matrix.names.iter().zip(&matrix.matrix) {
+            let _ = writeln!(
+                out,
+                "\t\t{} {}",
+                quote_label(name, options.quoting),
+                row.iter().format(" ")
+            );
+        }
+        out.push_str("\t;\nEND;\n\n");
+    }
+
+    out.push_str("BEGIN TREES;\n");
+    let _ = writeln!(
+        out,
+        "\tTREE tree1 = [&U] {}",
+        annotated(tree, sizes).to_newick(options)
+    );
+    out.push_str("END;\n");
+    out
+}
+
+/// Write the tree as phyloXML, with genome sizes as leaf properties
+pub fn to_phyloxml(tree: &Tree, sizes: &GenomeSizes, options: &NewickOptions) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(
+        "<phyloxml xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+         xmlns=\"http://www.phyloxml.org\" \
+         xsi:schemaLocation=\"http://www.phyloxml.org http://www.phyloxml.org/1.10/phyloxml.xsd\">\n",
+    );
+    out.push_str("  <phylogeny rooted=\"false\">\n");
+
+    // (node, depth, closing)
+    let mut stack: Vec<(NodeId, usize, bool)> = vec![(tree.root(), 2, false)];
+    while let Some((id, depth, closing)) = stack.pop() {
+        let indent = "  ".repeat(depth);
+        if closing {
+            let _ = writeln!(out, "{}</clade>", indent);
+            continue;
+        }
+        let node = tree.node(id);
+        let _ = writeln!(out, "{}<clade>", indent);
+        if let Some(label) = &node.label {
+            let _ = writeln!(out, "{}  <name>{}</name>", indent, escape_xml(label));
+        }
+        if let Some(length) = node.length {
+            let _ = writeln!(
+                out,
+                "{}  <branch_length>{}</branch_length>",
+                indent,
+                format_length(length, options.precision)
+            );
+        }
+        if let Some(support) = node.support {
+            let _ = writeln!(
+                out,
+                "{}  <confidence type=\"support\">{}</confidence>",
+                indent, support
+            );
+        }
+        if let Some(size) = node.label.as_ref().and_then(|l| sizes.get(l)) {
+            let _ = writeln!(
+                out,
+                "{}  <property ref=\"cedar:genome_size\" datatype=\"xsd:integer\" \
+                 applies_to=\"clade\">{}</property>",
+                indent, size
+            );
+        }
+        stack.push((id, depth, true));
+        for &child in node.children.iter().rev() {
+            stack.push((child, depth + 1, false));
+        }
+    }
+
+    out.push_str("  </phylogeny>\n</phyloxml>\n");
+    out
+}
+
+/// Write the tree as NeXML, with genome sizes as leaf metadata
+pub fn to_nexml(tree: &Tree, sizes: &GenomeSizes, options: &NewickOptions) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str(
+        "<nex:nexml xmlns:nex=\"http://www.nexml.org/2009\" \
+         xmlns=\"http://www.nexml.org/2009\" \
+         xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+         xmlns:xsd=\"http://www.w3.org/2001/XMLSchema#\" \
+         xmlns:cedar=\"https://github.com/Ebedthan/cedar#\" version=\"0.9\">\n",
+    );
+
+    out.push_str("  <otus id=\"otus1\">\n");
+    let leaves = tree.leaves();
+    for &id in &leaves {
+        let label = tree.node(id).label.as_deref().unwrap_or("");
+        match sizes.get(label) {
+            Some(size) => {
+                let _ = writeln!(
+                    out,
+                    "    <otu id=\"otu{}\" label=\"{}\">\n      \
+                     <meta xsi:type=\"nex:LiteralMeta\" id=\"meta{}\" \
+                     property=\"cedar:genome_size\" datatype=\"xsd:integer\" content=\"{}\"/>\n    \
+                     </otu>",
+                    id,
+                    escape_xml(label),
+                    id,
+                    size
+                );
+            }
+            None => {
+                let _ = writeln!(
+                    out,
+                    "    <otu id=\"otu{}\" label=\"{}\"/>",
+                    id,
+                    escape_xml(label)
+                );
+            }
+        }
+    }
+    out.push_str("  </otus>\n");
+
+    out.push_str("  <trees id=\"trees1\" otus=\"otus1\">\n");
+    out.push_str("    <tree id=\"tree1\" xsi:type=\"nex:FloatTree\">\n");
+    let order = tree.preorder();
+    for &id in &order {
+        let node = tree.node(id);
+        let _ = write!(out, "      <node id=\"n{}\"", id);
+        if id == tree.root() {
+            out.push_str(" root=\"true\"");
+        }
+        if node.is_leaf() {
+            let _ = write!(out, " otu=\"otu{}\"", id);
+        }
+        if let Some(label) = &node.label {
+            let _ = write!(out, " label=\"{}\"", escape_xml(label));
+        }
+        out.push_str("/>\n");
+    }
+    for &id in &order {
+        let node = tree.node(id);
+        if let Some(parent) = node.parent {
+            let _ = write!(
+                out,
+                "      <edge id=\"e{}\" source=\"n{}\" target=\"n{}\"",
+                id, parent, id
+            );
+            if let Some(length) = node.length {
+                let _ = write!(
+                    out,
+                    " length=\"{}\"",
+                    format_length(length, options.precision)
+                );
+            }
+            out.push_str("/>\n");
+        }
+    }
+    out.push_str("    </tree>\n  </trees>\n</nex:nexml>\n");
+    out
+}
+
+/// Escape XML special characters
+pub fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn example() -> (Tree, DistanceMatrix, GenomeSizes) {
+        let tree = Tree::from_newick("((A:0.1,B:0.2):0.05,C:0.3,D:0.4);").unwrap();
+        let matrix = DistanceMatrix {
+            matrix: vec![
+                vec![0.0, 0.3, 0.45, 0.55],
+                vec![0.3, 0.0, 0.55, 0.65],
+                vec![0.45, 0.55, 0.0, 0.7],
+                vec![0.55, 0.65, 0.7, 0.0],
+            ],
+            names: ["A", "B", "C", "D"].map(String::from).to_vec(),
+        };
+        let sizes = GenomeSizes::from([("A".to_string(), 4_000_000)]);
+        (tree, matrix, sizes)
+    }
+
+    #[test]
+    fn test_newick_annotations() {
+        let (tree, _, sizes) = example();
+        let newick = write_tree(
+            TreeFormat::Newick,
+            &tree,
+            None,
+            &sizes,
+            &NewickOptions::default(),
+        );
+        assert_eq!(
+            newick,
+            "((A[&genome_size=4000000]:0.1,B:0.2):0.05,C:0.3,D:0.4);"
+        );
+    }
+
+    #[test]
+    fn test_to_nexus() {
+        let (tree, matrix, sizes) = example();
+        let nexus = to_nexus(&tree, Some(&matrix), &sizes, &NewickOptions::default());
+        assert!(nexus.starts_with("#NEXUS\n"));
+        assert!(nexus.contains("\tDIMENSIONS NTAX=4;\n\tTAXLABELS\n\t\tA\n\t\tB\n\t\tC\n\t\tD\n"));
+        assert!(nexus.contains("BEGIN DISTANCES;"));
+        assert!(nexus.contains("\t\tC 0.45 0.55 0 0.7\n"));
+        assert!(nexus.contains("TREE tree1 = [&U] ((A[&genome_size=4000000]:0.1,B:0.2)"));
+
+        let nexus = to_nexus(&tree, None, &sizes, &NewickOptions::default());
+        assert!(!nexus.contains("BEGIN DISTANCES;"));
+    }
+
+    #[test]
+    fn test_to_phyloxml() {
+        let (tree, _, sizes) = example();
+        let xml = to_phyloxml(&tree, &sizes, &NewickOptions::default());
+        assert_eq!(xml.matches("<clade>").count(), tree.len());
+        assert_eq!(xml.matches("</clade>").count(), tree.len());
+        assert!(xml.contains("<name>A</name>\n          <branch_length>0.1</branch_length>"));
+        assert!(xml.contains(">4000000</property>"));
+    }
+
+    #[test]
+    fn test_to_nexml() {
+        let (tree, _, sizes) = example();
+        let xml = to_nexml(&tree, &sizes, &NewickOptions::default());
+        assert_eq!(xml.matches("<otu ").count(), 4);
+        assert_eq!(xml.matches("<node ").count(), tree.len());
+        assert_eq!(xml.matches("<edge ").count(), tree.len() - 1);
+        assert!(xml.contains("content=\"4000000\""));
+    }
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(escape_xml("a<b & 'c'"), "a&lt;b &amp; &apos;c&apos;");
+    }
+}
@@ -0,0 +1,311 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+
+use finch::{
+    distance::distance,
+    serialization::{Sketch, SketchDistance},
+};
+use rayon::prelude::*;
+
+use crate::dist;
+use crate::error::Error;
+
+/// Default number of candidates scored per query, see
+/// [`SketchIndex::max_candidates`]
+pub const DEFAULT_CANDIDATES: usize = 100;
+
+/// First bytes of an index file, followed by the format version
+const MAGIC: &[u8; 8] = b"CEDARIDX";
+const VERSION: u32 = 1;
+
+/// Inverted index from MinHash values to the reference sketches holding them.
+///
+/// References are ranked by the number of hashes they share with a query and
+/// only the best ranked ones are scored; references sharing no hash are at
+/// Mash distance 1 and never among the nearest.
+///
+/// The index is kept as sorted arrays so that [`SketchIndex::write`] and
+/// [`SketchIndex::read`] save building it again for the same references.
+pub struct SketchIndex {
+    sketches: Vec<Sketch>,
+    /// Distinct hashes, sorted
+    hashes: Vec<u64>,
+    /// References holding `hashes[i]` are `postings[offsets[i]..offsets[i + 1]]`
+    offsets: Vec<u64>,
+    postings: Vec<u32>,
+    max_candidates: usize,
+}
+
+impl SketchIndex {
+    pub fn new(sketches: Vec<Sketch>) -> Self {
+        let mut pairs: Vec<(u64, u32)> = sketches
+            .iter()
+            .enumerate()
+            .flat_map(|(i, sketch)| sketch.hashes.iter().map(move |kmer| (kmer.hash, i as u32)))
+            .collect();
+        pairs.par_sort_unstable();
+
+        let mut hashes = Vec::new();
+        let mut offsets = Vec::new();
+        for (i, &(hash, _)) in pairs.iter().enumerate() {
+            if hashes.last() != Some(&hash) {
+                hashes.push(hash);
+                offsets.push(i as u64);
+            }
+        }
+        offsets.push(pairs.len() as u64);
+        SketchIndex {
+            sketches,
+            hashes,
+            offsets,
+            postings: pairs.into_iter().map(|(_, i)| i).collect(),
+            max_candidates: DEFAULT_CANDIDATES,
+        }
+    }
+
+    /// Read an index written by [`SketchIndex::write`] for `sketches`
+    pub fn read(input: &mut dyn Read, sketches: Vec<Sketch>) -> anyhow::Result<Self> {
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC || read_u64s(input, 1)? != [u64::from(VERSION)] {
+            return Err(Error::Input("Not a cedar sketch index".to_string()).into());
+        }
+        let header = read_u64s(input, 4)?;
+        if header[..2] != [fingerprint(&sketches), sketches.len() as u64] {
+            return Err(Error::Input(
+                "The index was built for other reference sketches".to_string(),
+            )
+            .into());
+        }
+        let hashes = read_u64s(input, header[2] as usize)?;
+        let offsets = read_u64s(input, hashes.len() + 1)?;
+        let postings: Vec<u32> = read_values(input, header[3] as usize, 4)?
+            .chunks_exact(4)
+            .map(|chunk| u32::from_le_bytes(chunk.try_into().expect("4 bytes")))
+            .collect();
+        if offsets.first() != Some(&0)
+            || offsets.last() != Some(&(postings.len() as u64))
+            || offsets.windows(2).any(|w| w[0] > w[1])
+            || postings.iter().any(|&i| i as usize >= sketches.len())
+        {
+            return Err(Error::Input("Corrupted sketch index".to_string()).into());
+        }
+        Ok(SketchIndex {
+            sketches,
+            hashes,
+            offsets,
+            postings,
+            max_candidates: DEFAULT_CANDIDATES,
+        })
+    }
+
+    /// Write the index, without the sketches, in a binary format checked
+    /// against the sketches when it is read back
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        let header = [
+            u64::from(VERSION),
+            fingerprint(&self.sketches),
+            self.sketches.len() as u64,
+            self.hashes.len() as u64,
+            self.postings.len() as u64,
+        ];
+        let values = header.iter().chain(&self.hashes).chain(&self.offsets);
+        for value in values {
+            out.write_all(&value.to_le_bytes())?;
+        }
+        for posting in &self.postings {
+            out.write_all(&posting.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Score at most `max_candidates` references per query (or as many as
+    /// the number of requested neighbours, if larger), those sharing the
+    /// most hashes with it
+    pub fn max_candidates(mut self, max_candidates: usize) -> Self {
+        self.max_candidates = max_candidates;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.sketches.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sketches.is_empty()
+    }
+
+    pub fn sketches(&self) -> &[Sketch] {
+        &self.sketches
+    }
+
+    /// References sharing hashes with `query`, with the number of shared hashes
+    pub fn candidates(&self, query: &Sketch) -> Vec<(usize, usize)> {
+        let mut shared: HashMap<usize, usize> = HashMap::new();
+        for kmer in &query.hashes {
+            if let Ok(h) = self.hashes.binary_search(&kmer.hash) {
+                let postings = self.offsets[h] as usize..self.offsets[h + 1] as usize;
+                for &i in &self.postings[postings] {
+                    *shared.entry(i as usize).or_default() += 1;
+                }
+            }
+        }
+        let mut candidates: Vec<(usize, usize)> = shared.into_iter().collect();
+        candidates.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        candidates
+    }
+
+    /// The `k` references closest to `query` among its best candidates, with
+    /// the distances computed by `finch::distance`, closest first
+    pub fn nearest(&self, query: &Sketch, k: usize) -> Vec<SketchDistance> {
+        let mut candidates = self.candidates(query);
+        candidates.truncate(self.max_candidates.max(k));
+        let mut hits: Vec<SketchDistance> = candidates
+            .par_iter()
+            .filter_map(|&(i, _)| distance(query, &self.sketches[i], false).ok())
+            .collect();
+        dist::sort_hits(&mut hits);
+        hits.truncate(k);
+        hits
+    }
+}
+
+/// Checksum of the names and hashes of `sketches`, in order
+fn fingerprint(sketches: &[Sketch]) -> u64 {
+    const PRIME: u64 = 0x100000001b3;
+    let mut checksum: u64 = 0xcbf29ce484222325;
+    for sketch in sketches {
+        for &byte in sketch.name.as_bytes() {
+            checksum = (checksum ^ u64::from(byte)).wrapping_mul(PRIME);
+        }
+        for kmer in &sketch.hashes {
+            checksum = (checksum ^ kmer.hash).wrapping_mul(PRIME);
+        }
+    }
+    checksum
+}
+
+/// Read `count` values of `width` bytes, failing at the end of `input`
+/// rather than allocating for a corrupted count
+fn read_values(input: &mut dyn Read, count: usize, width: usize) -> io::Result<Vec<u8>> {
+    let len = count.saturating_mul(width);
+    let mut bytes = Vec::new();
+    input.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(bytes)
+}
+
+fn read_u64s(input: &mut dyn Read, count: usize) -> io::Result<Vec<u64>> {
+    Ok(read_values(input, count, 8)?
+        .chunks_exact(8)
+        .map(|chunk| u64::from_le_bytes(chunk.try_into().expect("8 bytes")))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_matches_brute_force() {
+        let references = crate::sketch::read_sketches(&[
+            "test/sketches/bacam.fna.msh",
+            "test/sketches/bacsp.fna.msh",
+        ])
+        .unwrap();
+        let index = SketchIndex::new(references.clone());
+        assert_eq!(index.len(), 2);
+
+        for query in &references {
+            let expected = dist::query_hits(query, &references, Some(0.999), None);
+            let found = index.nearest(query, 10);
+            assert_eq!(found.len(), expected.len());
+            for (a, b) in found.iter().zip(&expected) {
+                assert_eq!(a.reference, b.reference);
+                assert_eq!(a.mash_distance, b.mash_distance);
+            }
+            assert_eq!(index.nearest(query, 1)[0].reference, query.name);
+        }
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let references = crate::sketch::read_sketches(&[
+            "test/sketches/bacam.fna.msh",
+            "test/sketches/bacsp.fna.msh",
+        ])
+        .unwrap();
+        let index = SketchIndex::new(references.clone());
+        let mut file = Vec::new();
+        index.write(&mut file).unwrap();
+
+        let read = SketchIndex::read(&mut file.as_slice(), references.clone()).unwrap();
+        assert_eq!(read.hashes, index.hashes);
+        assert_eq!(read.offsets, index.offsets);
+        assert_eq!(read.postings, index.postings);
+        for query in &references {
+            assert_eq!(read.candidates(query), index.candidates(query));
+        }
+
+        let others = references[..1].to_vec();
+        let err = SketchIndex::read(&mut file.as_slice(), others)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("other reference sketches"));
+        let truncated = &file[..file.len() - 1];
+        assert!(SketchIndex::read(&mut &truncated[..], references.clone()).is_err());
+        assert!(SketchIndex::read(&mut &b"not an index"[..], references).is_err());
+    }
+
+    #[test]
+    fn test_nearest_among_many_references() {
+        let params = crate::sketch::mash_params(21, 1000, 1, 42);
+        let sketch = |path: &str| {
+            crate::sketch::sketch_file_multi(path, std::slice::from_ref(&params))
+                .unwrap()
+                .remove(0)
+        };
+        let bacam = sketch("test/bacam.fna");
+        let bacsp = sketch("test/bacsp.fna");
+
+        // Decoys keep a decreasing part of the hashes of bacam, bacsp the
+        // rest; only the top candidates are scored
+        let mut references = Vec::new();
+        for i in 0..200 {
+            let mut decoy = bacsp.clone();
+            decoy.name = format!("decoy{}", i);
+            let kept = 400 - i;
+            decoy.hashes = bacam.hashes[..kept]
+                .iter()
+                .chain(&bacsp.hashes[kept..])
+                .cloned()
+                .collect();
+            decoy.hashes.sort_by_key(|h| h.hash);
+            references.push(decoy);
+        }
+        let mut closest = bacam.clone();
+        closest.name = "closest".to_string();
+        closest.hashes.truncate(900);
+        closest.hashes.extend(bacsp.hashes[900..].iter().cloned());
+        closest.hashes.sort_by_key(|h| h.hash);
+        references.insert(123, closest);
+
+        let index = SketchIndex::new(references.clone()).max_candidates(10);
+        let found = index.nearest(&bacam, 3);
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].reference, "closest");
+        assert_eq!(found[1].reference, "decoy0");
+        let expected = dist::query_hits(&bacam, &references, None, Some(3));
+        for (a, b) in found.iter().zip(&expected) {
+            assert_eq!(a.reference, b.reference);
+        }
+    }
+}
@@ -0,0 +1,350 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Cursor, Read};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use bzip2::read::BzDecoder;
+use flate2::read::MultiGzDecoder;
+use itertools::Itertools;
+use xz2::read::XzDecoder;
+
+use crate::dist;
+use crate::error::Error;
+
+/// Path standing for the standard input
+pub const STDIN: &str = "-";
+
+/// Extensions of the sequence files picked up in input directories
+pub const FASTA_EXTENSIONS: &[&str] = &["fa", "fasta", "fna", "ffn", "faa", "fas"];
+
+/// Compression suffixes ignored when matching extensions
+const COMPRESSION_EXTENSIONS: &[&str] = &["gz", "xz", "bz2"];
+
+/// A genome file with an optional label used as its name in outputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputFile {
+    pub path: String,
+    pub label: Option<String>,
+}
+
+impl InputFile {
+    pub fn new(path: impl Into<String>) -> Self {
+        InputFile {
+            path: path.into(),
+            label: None,
+        }
+    }
+
+    /// Name of the genome in matrices and trees
+    pub fn name(&self) -> String {
+        self.label
+            .clone()
+            .unwrap_or_else(|| dist::sketch_name(&self.path))
+    }
+}
+
+/// Content of the standard input, read once and kept for every later pass.
+///
+/// The whole stream is buffered as read (compressed, if it is), so a genome
+/// piped on stdin takes its full size in memory for the whole run.
+static STDIN_CONTENT: OnceLock<Result<Vec<u8>, String>> = OnceLock::new();
+
+fn stdin_content() -> io::Result<&'static [u8]> {
+    STDIN_CONTENT
+        .get_or_init(|| {
+            let mut content = Vec::new();
+            io::stdin()
+                .lock()
+                .read_to_end(&mut content)
+                .map(|_| content)
+                .map_err(|e| e.to_string())
+        })
+        .as_deref()
+        .map_err(|e| io::Error::other(e.clone()))
+}
+
+/// Open a sequence file, or the standard input for [`STDIN`], and
+/// decompress it if it starts with gzip, bzip2 or xz magic bytes.
+///
+/// The standard input is kept in memory, so that it can be opened again
+/// when it has to be read twice (its size is unknown, so k computed from
+/// it may not be among the candidates sketched in the first pass); large
+/// genomes are better given as files.
+pub fn open(path: &str) -> anyhow::Result<Box<dyn BufRead + Send>> {
+    #[cfg(test)]
+    tests::count_open(path);
+    let mut reader: Box<dyn BufRead + Send> = if path == STDIN {
+        Box::new(Cursor::new(
+            stdin_content().map_err(|e| Error::io("stdin", e))?,
+        ))
+    } else {
+        Box::new(BufReader::new(
+            File::open(path).map_err(|e| Error::io(path, e))?,
+        ))
+    };
+    let magic = reader.fill_buf().map_err(|e| Error::io(path, e))?;
+    Ok(match magic {
+        [0x1f, 0x8b, ..] => Box::new(BufReader::new(MultiGzDecoder::new(reader))),
+        [b'B', b'Z', b'h', ..] => Box::new(BufReader::new(BzDecoder::new(reader))),
+        [0xfd, b'7', b'z', b'X', b'Z', ..] => Box::new(BufReader::new(XzDecoder::new(reader))),
+        _ => reader,
+    })
+}
+
+/// Smallest and largest genome size a sequence file can hold, guessed from
+/// its size on disk: a plain file holds at most as many bases as bytes and
+/// at least a third of them (FASTQ qualities and headers), a compressed file
+/// up to five times more; `None` for the standard input or a missing file
+pub fn genome_size_bounds(path: &str) -> Option<(u64, u64)> {
+    if path == STDIN {
+        return None;
+    }
+    let len = fs::metadata(path).ok()?.len();
+    let compressed = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| COMPRESSION_EXTENSIONS.contains(&e));
+    Some(if compressed {
+        (len, len * 5)
+    } else {
+        (len / 3, len)
+    })
+}
+
+/// First character of a sequence stream after leading whitespace, without
+/// consuming it: `>` for FASTA, `@` for FASTQ, `None` for an empty stream
+pub fn peek_format<R: BufRead + ?Sized>(reader: &mut R) -> io::Result<Option<u8>> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(None);
+        }
+        match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+            Some(i) => {
+                let first = buf[i];
+                reader.consume(i);
+                return Ok(Some(first));
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// Read a file of filenames: one path per line, optionally followed by a tab
+/// and a label. Empty lines and lines starting with `#` are skipped.
+pub fn read_fofn(path: &str) -> anyhow::Result<Vec<InputFile>> {
+    let content = fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+    let mut files = Vec::new();
+    for line in content.lines() {
+        let line = line.trim_end();
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(2, '\t');
+        let file = fields.next().unwrap_or_default().trim();
+        let label = fields.next().map(str::trim).filter(|l| !l.is_empty());
+        files.push(InputFile {
+            path: file.to_string(),
+            label: label.map(str::to_string),
+        });
+    }
+    Ok(files)
+}
+
+/// Whether `path` ends with one of `extensions`, compressed or not
+pub fn has_extension(path: &Path, extensions: &[String]) -> bool {
+    let mut path = path.to_path_buf();
+    if path
+        .extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| COMPRESSION_EXTENSIONS.contains(&e))
+    {
+        path.set_extension("");
+    }
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.iter().any(|x| x.eq_ignore_ascii_case(e)))
+}
+
+/// Sequence files of `dir` with one of `extensions`, sorted by path, and
+/// those of its subdirectories if `recursive`
+pub fn list_dir(
+    dir: &str,
+    recursive: bool,
+    extensions: &[String],
+) -> anyhow::Result<Vec<InputFile>> {
+    let mut files = Vec::new();
+    let mut pending = vec![Path::new(dir).to_path_buf()];
+    while let Some(current) = pending.pop() {
+        let entries =
+            fs::read_dir(&current).map_err(|e| Error::io(current.to_string_lossy(), e))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|e| Error::io(current.to_string_lossy(), e))?
+                .path();
+            if path.is_dir() {
+                if recursive {
+                    pending.push(path);
+                }
+            } else if has_extension(&path, extensions) {
+                files.push(path.to_string_lossy().into_owned());
+            }
+        }
+    }
+    files.sort();
+    Ok(files.into_iter().map(InputFile::new).collect())
+}
+
+/// Fail if two genomes would get the same name, which would merge them in
+/// the distance matrix
+pub fn check_unique_names(files: &[InputFile]) -> anyhow::Result<()> {
+    // Sketches are matched to genomes by file stem, labels are applied last
+    let stems = files
+        .iter()
+        .map(|f| dist::sketch_name(&f.path))
+        .duplicates();
+    let names = files.iter().map(InputFile::name).duplicates();
+    let duplicates: Vec<String> = stems.chain(names).unique().collect();
+    if !duplicates.is_empty() {
+        return Err(Error::Input(format!(
+            "Several genomes share the same name: {}",
+            duplicates.join(", ")
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Labels of the labelled files, by genome name
+pub fn labels(files: &[InputFile]) -> HashMap<String, String> {
+    files
+        .iter()
+        .filter_map(|f| Some((dist::sketch_name(&f.path), f.label.clone()?)))
+        .collect()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    /// Number of times each path was opened, to check single-pass reading
+    static OPENED: Mutex<Option<HashMap<String, usize>>> = Mutex::new(None);
+
+    pub(crate) fn count_open(path: &str) {
+        let mut opened = OPENED.lock().unwrap();
+        *opened
+            .get_or_insert_with(HashMap::new)
+            .entry(path.to_string())
+            .or_default() += 1;
+    }
+
+    pub(crate) fn open_count(path: &str) -> usize {
+        let opened = OPENED.lock().unwrap();
+        opened
+            .as_ref()
+            .and_then(|o| o.get(path))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn test_read_fofn() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(
+            file,
+            "# genomes\ntest/bacam.fna\tB. amyloliquefaciens\n\ntest/bacsp.fna"
+        )
+        .unwrap();
+        let files = read_fofn(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].name(), "B. amyloliquefaciens");
+        assert_eq!(files[1], InputFile::new("test/bacsp.fna"));
+        assert_eq!(files[1].name(), "bacsp");
+        assert_eq!(labels(&files)["bacam"], "B. amyloliquefaciens");
+        assert!(read_fofn("test/missing.txt").is_err());
+    }
+
+    #[test]
+    fn test_list_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let sub = dir.path().join("sub");
+        fs::create_dir(&sub).unwrap();
+        for path in [
+            dir.path().join("a.fna"),
+            dir.path().join("b.fa.gz"),
+            dir.path().join("notes.txt"),
+            sub.join("c.fasta"),
+        ] {
+            fs::write(path, ">x\nACGT\n").unwrap();
+        }
+        let extensions: Vec<String> = FASTA_EXTENSIONS.iter().map(|e| e.to_string()).collect();
+        let root = dir.path().to_str().unwrap();
+        let names = |files: Vec<InputFile>| files.iter().map(InputFile::name).collect::<Vec<_>>();
+
+        assert_eq!(
+            names(list_dir(root, false, &extensions).unwrap()),
+            ["a", "b.fa"]
+        );
+        assert_eq!(
+            names(list_dir(root, true, &extensions).unwrap()),
+            ["a", "b.fa", "c"]
+        );
+        assert_eq!(
+            names(list_dir(root, true, &["txt".to_string()]).unwrap()),
+            ["notes"]
+        );
+    }
+
+    #[test]
+    fn test_open_and_peek() {
+        let mut reader = open("test/bacam.fna").unwrap();
+        assert_eq!(peek_format(&mut reader).unwrap(), Some(b'>'));
+        // Peeking does not consume the header
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        assert!(line.starts_with('>'));
+
+        let mut file = tempfile::Builder::new().suffix(".gz").tempfile().unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(&mut file, Default::default());
+        std::io::Write::write_all(&mut encoder, b"\n\n>x\nACGT\n").unwrap();
+        encoder.finish().unwrap();
+        let mut reader = open(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(peek_format(&mut reader).unwrap(), Some(b'>'));
+
+        assert_eq!(
+            peek_format(&mut Cursor::new(" \n@r\n")).unwrap(),
+            Some(b'@')
+        );
+        assert_eq!(peek_format(&mut Cursor::new("\n\n")).unwrap(), None);
+    }
+
+    #[test]
+    fn test_check_unique_names() {
+        let files = [InputFile::new("x/a.fna"), InputFile::new("y/a.fna")];
+        assert!(check_unique_names(&files).is_err());
+        assert!(check_unique_names(&files[..1]).is_ok());
+        let labelled = [
+            InputFile {
+                path: "x/a.fna".to_string(),
+                label: Some("a".to_string()),
+            },
+            InputFile {
+                path: "x/b.fna".to_string(),
+                label: Some("a".to_string()),
+            },
+        ];
+        assert!(check_unique_names(&labelled[..1]).is_ok());
+        assert!(check_unique_names(&labelled).is_err());
+    }
+}
@@ -0,0 +1,36 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+//! Rapid genome phylogenies from MinHash sketches.
+//!
+//! [`Pipeline`] runs the whole workflow (genome statistics, sketching,
+//! Mash distances and neighbor-joining) and returns structured results;
+//! the other modules expose each step on its own.
+
+pub mod ascii;
+pub mod cluster;
+pub mod derep;
+pub mod dist;
+pub mod error;
+pub mod filter;
+pub mod formats;
+pub mod index;
+pub mod inputs;
+pub mod nj;
+pub mod pipeline;
+pub mod place;
+pub mod progress;
+pub mod protein;
+pub mod report;
+pub mod sketch;
+pub mod stats;
+pub mod svg;
+pub mod tree;
+pub mod units;
+pub mod utils;
+
+pub use error::Error;
+pub use pipeline::{Config, OnError, Pipeline, PipelineResult, TreeMethod};
+pub use tree::Tree;
@@ -3,109 +3,861 @@
 // This file may not be copied, modified, or distributed except according
 // to those terms.
 
-pub mod cli;
-pub mod dist;
-pub mod sketch;
-pub mod utils;
-use clap::Parser;
+mod cli;
 
+use cedar::pipeline::{Pipeline, PipelineResult, TreeMethod};
+use cedar::stats::GenomeStats;
+use cedar::utils::Operation;
+use cedar::{
+    ascii, cluster, derep, dist, error, filter, formats, index, inputs, place, progress, protein,
+    report, sketch, stats, svg, tree, utils, Error,
+};
+use clap::Parser;
 use rayon::prelude::*;
-use std::{fs, process};
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::time::Instant;
+use std::{fs, io, path::Path, process};
 
 use anyhow::Context;
-use finch::serialization::Sketch;
+use finch::{serialization::Sketch, sketch_schemes::SketchParams};
+use itertools::Itertools;
+
+/// Report the error chain on stderr and exit with the code of its kind, see
+/// [`Error::exit_code`]
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("Error: {:#}", err);
+        process::exit(error::exit_code(&err));
+    }
+}
 
-fn main() -> anyhow::Result<()> {
+fn run() -> anyhow::Result<()> {
     // Read command-line arguments
     let cli = cli::Cli::parse();
-    let filenames = cli.input.as_slice();
+    init_logging(cli.command.as_ref().map_or(cli.log, cli::Command::log));
+    if let Some(command) = &cli.command {
+        return match command {
+            cli::Command::View(args) => view(args),
+            cli::Command::Render(args) => render(args),
+            cli::Command::Cluster(args) => cluster(args),
+            cli::Command::Dereplicate(args) => dereplicate(args),
+            cli::Command::Place(args) => place(args),
+            cli::Command::Dist(args) => dist(args),
+            cli::Command::Search(args) => search(args),
+            cli::Command::Sketch(args) => sketch_genomes(args),
+            cli::Command::Info(args) => info(args),
+        };
+    }
+    let genomes = collect_inputs(&cli.input, &cli.source)?;
+    let filenames: Vec<String> = genomes.iter().map(|g| g.path.clone()).collect();
+    let filenames = filenames.as_slice();
+
+    // Configure Rayon thread pool
+    init_thread_pool(cli.threads)?;
+
+    // Create temporary directory
+    let tempdir = "darwin_tmp";
+    fs::create_dir_all(tempdir).map_err(|e| Error::io(tempdir, e))?;
 
-    // Validate inputs
-    if let Err(e) = utils::validate_inputs(&cli.input) {
-        eprintln!("Input validation error: {e}");
-        process::exit(1);
+    // Sketch, compute distances and build the tree of each k-mer size
+    let pipeline = pipeline_for(filenames, &cli.sketch)
+        .tree_method(if cli.canonical {
+            TreeMethod::Canonical
+        } else {
+            TreeMethod::Rapid
+        })
+        .negative_branches(cli.negative_branches)
+        .threads(cli.threads)
+        .sketch_dir(tempdir)
+        .labels(inputs::labels(&genomes))
+        .min_genome_size(cli.filter.min_genome_size)
+        .max_genome_size(cli.filter.max_genome_size)
+        .max_n_fraction(cli.filter.max_n_fraction)
+        .min_contig_length(cli.filter.min_contig_length)
+        .mask_low_complexity(cli.filter.mask_low_complexity)
+        .on_error(cli.on_error);
+    let result = pipeline.run()?;
+    let output_start = Instant::now();
+    // Files written, checksummed in the run report
+    let mut outputs: Vec<String> = Vec::new();
+    report_failures(&result.failures, cli.failures.as_deref())?;
+    outputs.extend(cli.failures.clone());
+    report_filters(&result.filters, &result.outlier_inputs(), &cli.filter)?;
+    outputs.extend(cli.filter.filter_report.clone());
+    let kmer_sizes: Vec<u8> = result.runs.iter().map(|run| run.k).collect();
+    print_stats(&result.stats, &kmer_sizes, &cli.sketch);
+    write_stats_table(&cli.stats, &result.genome_stats())?;
+    outputs.extend(cli.stats.output.clone());
+    if result.runs.len() > 1 {
+        outputs.extend(compare_kmer_sizes(&result, &cli)?);
+    }
+
+    let stats = &result.stats;
+    let matrix = result.matrix();
+    let tree = result
+        .tree()
+        .ok_or_else(|| Error::Tree("The pipeline built no tree".to_string()))?;
+    let summary = result.main().negative_branches;
+    if summary.changed > 0 {
+        log::info!(
+            "Negative branches: {} changed, total length shifted: {}",
+            summary.changed,
+            summary.shifted
+        );
     }
+    let newick_options = tree::NewickOptions {
+        precision: cli.precision,
+        ..Default::default()
+    };
+    let genome_sizes: formats::GenomeSizes = if cli.annotate {
+        result
+            .names
+            .iter()
+            .zip(stats)
+            .map(|(name, stat)| (name.clone(), stat.length))
+            .collect()
+    } else {
+        formats::GenomeSizes::new()
+    };
+    let content = formats::write_tree(
+        cli.tree_format,
+        tree,
+        Some(matrix),
+        &genome_sizes,
+        &newick_options,
+    );
 
-    let stats: Vec<(String, usize)> = filenames
+    // 3.2. Output tree
+    let stdout_checksum = match &cli.output {
+        Some(path) => {
+            outputs.push(path.clone());
+            None
+        }
+        None => Some(report::sha256(format!("{}\n", content).as_bytes())),
+    };
+    utils::output_tree(cli.output.clone(), content)?;
+    if let Some(path) = &cli.svg {
+        let options = svg_options(&cli.render)?;
+        fs::write(path, svg::render_svg(tree, &options))
+            .with_context(|| format!("Could not write SVG: {}", path))?;
+        outputs.push(path.clone());
+    }
+    if cli.print_tree {
+        eprint!(
+            "{}",
+            ascii::render_ascii(tree, &ascii::AsciiOptions::default())
+        );
+    }
+
+    // Manage tempdir and tempfiles
+    utils::manage_tempdir(cli.keep, matrix, tempdir)?;
+
+    if let Some(path) = &cli.report {
+        let mut timings = result.timings.clone();
+        timings.add("output", output_start.elapsed());
+        let mut checksums: Vec<(String, String)> = outputs
+            .iter()
+            .map(|path| Ok((path.clone(), report::sha256_file(path)?)))
+            .collect::<anyhow::Result<_>>()?;
+        checksums.extend(stdout_checksum.map(|sha256| ("-".to_string(), sha256)));
+        let excluded = result
+            .failures
+            .iter()
+            .map(|f| (f.input.clone(), f.reason.clone()))
+            .chain(
+                result
+                    .filters
+                    .iter()
+                    .filter_map(|f| Some((f.input.clone(), f.excluded.clone()?))),
+            )
+            .collect();
+        let run_report = report::RunReport {
+            command: std::env::args().collect(),
+            parameters: pipeline.config().to_json(&kmer_sizes),
+            genomes: result.genome_stats(),
+            outliers: result.outliers.clone(),
+            excluded,
+            timings,
+            outputs: checksums,
+        };
+        let mut out = utils::open_output(Some(path))?;
+        run_report.write(&mut out).map_err(|e| Error::io(path, e))?;
+        out.flush()?;
+    }
+
+    Ok(())
+}
+
+/// Log with timestamps on stderr at the level set by -v/-q, and draw
+/// progress bars unless quiet, below the log lines
+fn init_logging(args: cli::LogArgs) {
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => log::LevelFilter::Warn,
+        (false, 0) => log::LevelFilter::Info,
+        (false, 1) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    };
+    let logger = env_logger::Builder::new()
+        .filter_level(level)
+        .format_target(false)
+        .build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(progress::Logger(logger))).expect("logger is set once");
+    progress::set_enabled(!args.quiet);
+}
+
+/// Genomes listed on the command line, in a file of filenames and in an
+/// input directory, in this order
+fn collect_inputs(
+    input: &[String],
+    source: &cli::InputSourceArgs,
+) -> anyhow::Result<Vec<inputs::InputFile>> {
+    let mut genomes: Vec<inputs::InputFile> = input
         .iter()
-        .map(|f| utils::get_seq_stats(f))
-        .collect::<anyhow::Result<_>>()?;
+        .map(|f| inputs::InputFile::new(f.as_str()))
+        .collect();
+    if let Some(fofn) = &source.fofn {
+        genomes.extend(inputs::read_fofn(fofn)?);
+    }
+    if let Some(dir) = &source.input_dir {
+        let found = inputs::list_dir(dir, source.recursive, &source.extension)?;
+        if found.is_empty() {
+            return Err(Error::Input(format!(
+                "No file with extension {} in {}",
+                source.extension.iter().format(", "),
+                dir
+            ))
+            .into());
+        }
+        genomes.extend(found);
+    }
+    inputs::check_unique_names(&genomes)?;
+    Ok(genomes)
+}
 
-    for stat in &stats {
-        println!(
+fn init_thread_pool(threads: usize) -> anyhow::Result<()> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build_global()
+        .context("Could not configure the thread pool")
+}
+
+/// Pipeline configured from the sketching options
+fn pipeline_for(inputs: &[String], args: &cli::SketchArgs) -> Pipeline {
+    Pipeline::new(inputs.iter().cloned())
+        .kmer_sizes(args.kmer.clone())
+        .sketch_size(args.size)
+        .seed(args.seed)
+        .oversketch(args.oversketch)
+        .scaled(args.scaled)
+        .protein(args.protein)
+        .genome_size(args.genome_size)
+}
+
+/// Log the size of each genome and the k-mer sizes used
+fn print_stats(stats: &[utils::AssemblyStats], kmer_sizes: &[u8], args: &cli::SketchArgs) {
+    for stat in stats {
+        log::debug!(
             "Genome: {}, size: {}",
-            stat.0,
-            utils::format_genome_size(stat.1)
+            stat.id,
+            utils::format_genome_size(stat.length)
         );
     }
-    let outliers = utils::detect_outliers(&stats, 0.05)?;
-    if !outliers.is_empty() {
-        eprintln!("Error: outliers detected in genome sizes");
-        for outlier in outliers {
-            eprintln!(
-                "Genome {} with size {} negatively influence k selection with influential size",
-                outlier.0,
-                utils::format_genome_size(outlier.1)
+    match (args.kmer.len(), kmer_sizes) {
+        (0, [k]) if args.protein => log::info!("Amino-acid k-mer size: {}", k),
+        (0, [k]) => {
+            let (kind, genome_size) = match args.genome_size {
+                Some(size) => ("user-defined", size),
+                None => {
+                    let stats: Vec<utils::SeqStats> = stats.iter().map(|s| s.seq_stats()).collect();
+                    ("mean", utils::mean_genome_size(&stats) as usize)
+                }
+            };
+            log::info!(
+                "Computed k-mer size (with {} genome size: {} and probability: {}): {}",
+                kind,
+                utils::format_genome_size(genome_size),
+                0.01,
+                k
             );
         }
-        process::exit(1);
+        (_, [k]) => log::info!("User-defined k-mer size: {}", k),
+        _ => log::info!(
+            "User-defined k-mer sizes: {}",
+            kmer_sizes.iter().format(",")
+        ),
     }
-    let mut kmer_size = 0_u8;
-    if let Some(km) = cli.kmer {
-        println!("User-defined k-mer size: {}", km);
-    } else {
-        let mean_genome_size = &stats.iter().map(|x| x.1 as u32).sum::<u32>() / stats.len() as u32;
-        kmer_size = sketch::k_computing(mean_genome_size, 0.01);
-        println!(
-            "Computed k-mer size (with mean genome size: {} and probability: {}): {}",
-            utils::format_genome_size(mean_genome_size as usize),
-            0.01,
-            kmer_size
+}
+
+/// Report the skipped genomes on stderr, and write the failures report if
+/// requested
+fn report_failures(failures: &[error::Failure], path: Option<&str>) -> anyhow::Result<()> {
+    for failure in failures {
+        log::warn!("Skipped {}: {}", failure.input, failure.reason);
+    }
+    if let Some(path) = path {
+        let mut out = utils::open_output(Some(path))?;
+        error::write_failures(&mut out, failures).map_err(|e| Error::io(path, e))?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Report on stderr what the filters removed, and write the filter report
+/// if requested
+fn report_filters(
+    decisions: &[filter::FilterDecision],
+    outliers: &HashSet<&str>,
+    args: &cli::FilterArgs,
+) -> anyhow::Result<()> {
+    for decision in decisions {
+        let records = &decision.records;
+        if let Some(reason) = &decision.excluded {
+            log::warn!("Excluded {}: {}", decision.input, reason);
+        } else if records.dropped_records > 0 || records.masked_bases > 0 {
+            log::info!(
+                "Filtered {}: {} contigs dropped ({} bp), {} bp masked",
+                decision.input,
+                records.dropped_records,
+                records.dropped_bases,
+                records.masked_bases
+            );
+        }
+    }
+    if let Some(path) = &args.filter_report {
+        let mut out = utils::open_output(Some(path))?;
+        filter::write_report(&mut out, decisions, outliers)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Write the per-genome statistics table, if requested
+fn write_stats_table(args: &cli::StatsArgs, genomes: &[GenomeStats]) -> anyhow::Result<()> {
+    if let Some(path) = &args.output {
+        let mut out = utils::open_output(Some(path))?;
+        stats::write_stats(&mut out, genomes, args.stats_format)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+/// Compute genome statistics, choose k and sketch the sequence files.
+///
+/// Returns the assembly statistics of each kept genome, by input path, and
+/// its sketch.
+fn sketch_sequences(
+    filenames: &[String],
+    args: &cli::SketchArgs,
+) -> anyhow::Result<(HashMap<String, utils::AssemblyStats>, Vec<Sketch>)> {
+    if args.kmer.len() > 1 {
+        return Err(Error::Input(
+            "Several k-mer sizes are only supported when building a tree".to_string(),
+        )
+        .into());
+    }
+    let pipeline = pipeline_for(filenames, args).no_tree();
+    let mut sketched = pipeline.stats_and_sketches()?;
+    let kmer_sizes: Vec<u8> = sketched.per_k.iter().map(|(k, _)| *k).collect();
+    print_stats(&sketched.stats, &kmer_sizes, args);
+    let (_, sketches) = sketched.per_k.swap_remove(0);
+    let stats = sketched.inputs.into_iter().zip(sketched.stats).collect();
+    Ok((stats, sketches))
+}
+
+/// Write the matrix and tree of each k-mer size and report how much the
+/// tree topologies differ (Robinson-Foulds distance) on stderr
+fn compare_kmer_sizes(result: &PipelineResult, cli: &cli::Cli) -> anyhow::Result<Vec<String>> {
+    let mut written = Vec::new();
+    if let Some(dir) = &cli.per_k {
+        fs::create_dir_all(dir).with_context(|| format!("Could not create directory: {}", dir))?;
+        let newick_options = tree::NewickOptions {
+            precision: cli.precision,
+            ..Default::default()
+        };
+        for run in &result.runs {
+            let path = Path::new(dir).join(format!("k{}.phylip", run.k));
+            let mut out = utils::open_output(path.to_str())?;
+            dist::write_phylip(&mut out, &run.matrix)?;
+            out.flush()?;
+            written.push(path.to_string_lossy().into_owned());
+            if let Some(tree) = &run.tree {
+                let path = Path::new(dir).join(format!("k{}.nwk", run.k));
+                let path = path.to_string_lossy().into_owned();
+                utils::output_tree(Some(path.clone()), tree.to_newick(&newick_options))?;
+                written.push(path);
+            }
+        }
+
+        let matrices: Vec<(u8, speedytree::DistanceMatrix)> = result
+            .runs
+            .iter()
+            .map(|run| (run.k, run.matrix.clone()))
+            .collect();
+        let path = Path::new(dir).join("distances.tsv");
+        let mut out = utils::open_output(path.to_str())?;
+        dist::write_distances_per_k(&mut out, &matrices)?;
+        out.flush()?;
+        written.push(path.to_string_lossy().into_owned());
+    }
+
+    log::info!("Robinson-Foulds distances between k-mer sizes:");
+    let trees = result
+        .runs
+        .iter()
+        .filter_map(|run| Some((run.k, run.tree.as_ref()?)));
+    for ((ka, a), (kb, b)) in trees.tuple_combinations() {
+        let (rf, max) = tree::robinson_foulds(a, b)?;
+        log::info!(
+            "k={} vs k={}: {}/{} ({:.3})",
+            ka,
+            kb,
+            rf,
+            max,
+            rf as f64 / max.max(1) as f64
         );
     }
+    Ok(written)
+}
 
-    // Configure Rayon thread pool
-    rayon::ThreadPoolBuilder::new()
-        .num_threads(cli.threads)
-        .build_global()
-        .unwrap();
+/// Distance matrix from sequence files, sketch files or a PHYLIP matrix
+fn load_matrix(
+    inputs: &[String],
+    args: &cli::SketchArgs,
+) -> anyhow::Result<speedytree::DistanceMatrix> {
+    if let [path] = inputs {
+        if path.ends_with(".phylip") || path.ends_with(".phy") {
+            let file = fs::File::open(path).map_err(|e| Error::io(path, e))?;
+            return speedytree::DistanceMatrix::read_from_phylip(io::BufReader::new(file)).map_err(
+                |e| Error::Distance(format!("Invalid PHYLIP matrix {}: {}", path, e)).into(),
+            );
+        }
+    }
 
-    // Create temporary directory
-    let tempdir = "darwin_tmp";
-    fs::create_dir_all(tempdir).context(format!("Could not create temp directory: {}", tempdir))?;
-
-    // Step 1: Create sketches from sequences
-    // 1.1. Create sketches using CLI args and sketch::create_sketches function
-    let sketches_path = sketch::create_sketches(
-        filenames,
-        kmer_size,
-        cli.size,
-        cli.oversketch,
-        cli.seed,
-        tempdir,
-    )?;
-
-    // 1.2. Read created sketches files in a list
-    let sketches: Vec<Sketch> = sketches_path
-        .into_par_iter()
-        .flat_map(|path| finch::open_sketch_file(path).unwrap())
+    let sketches = if inputs.iter().all(|f| sketch::is_sketch_file(f)) {
+        sketch::read_sketches(inputs)?
+    } else {
+        utils::check_input_count(inputs.len(), Operation::Distances)?;
+        let (_, sketches) = sketch_sequences(inputs, args)?;
+        sketches
+    };
+
+    Ok(dist::distance_to_matrix(dist::compute_distances(sketches)))
+}
+
+/// Cluster genomes at a distance threshold
+fn cluster(args: &cli::ClusterArgs) -> anyhow::Result<()> {
+    init_thread_pool(args.threads)?;
+    let genomes = collect_inputs(&args.input, &args.source)?;
+    let filenames: Vec<String> = genomes.iter().map(|g| g.path.clone()).collect();
+    let mut matrix = load_matrix(&filenames, &args.sketch)?;
+    dist::relabel(&mut matrix, &inputs::labels(&genomes));
+    let threshold = match (args.threshold, args.ani) {
+        (Some(threshold), _) => threshold,
+        (None, Some(ani)) => cluster::ani_to_distance(ani),
+        (None, None) => unreachable!("clap requires a threshold or an ANI"),
+    };
+
+    let clustering = cluster::cluster(&matrix, args.linkage, threshold);
+    log::info!(
+        "{} genomes grouped in {} clusters at distance {:.4}",
+        matrix.size(),
+        clustering.n_clusters(),
+        threshold
+    );
+
+    let mut out = utils::open_output(args.output.as_deref())?;
+    cluster::write_clusters(&mut out, &matrix, &clustering)
+}
+
+/// Keep the best genome of each group of near-identical genomes
+fn dereplicate(args: &cli::DerepArgs) -> anyhow::Result<()> {
+    let genomes = collect_inputs(&args.input, &args.source)?;
+    let filenames: Vec<String> = genomes.iter().map(|g| g.path.clone()).collect();
+    utils::check_input_count(filenames.len(), Operation::Distances)?;
+    init_thread_pool(args.threads)?;
+
+    let (assembly_stats, sketches) = sketch_sequences(&filenames, &args.sketch)?;
+    let mut matrix = dist::distance_to_matrix(dist::compute_distances(sketches));
+    dist::relabel(&mut matrix, &inputs::labels(&genomes));
+
+    // Keep the path and assembly statistics of each genome by its name
+    let mut paths = HashMap::new();
+    let mut stats = HashMap::new();
+    for genome in &genomes {
+        if let Some(stat) = assembly_stats.get(&genome.path) {
+            stats.insert(genome.name(), stat.clone());
+            paths.insert(genome.name(), genome.path.as_str());
+        }
+    }
+    let quality = args
+        .quality
+        .as_deref()
+        .map(derep::read_quality)
+        .transpose()?;
+
+    let threshold = args
+        .threshold
+        .unwrap_or_else(|| cluster::ani_to_distance(args.ani));
+    let mut clustering = cluster::cluster(&matrix, args.linkage, threshold);
+    clustering.representatives = derep::select_representatives(
+        &clustering,
+        &matrix.names,
+        &stats,
+        quality.as_ref(),
+        args.select,
+    );
+    log::info!(
+        "{} genomes dereplicated into {} representatives at distance {:.4}",
+        matrix.size(),
+        clustering.n_clusters(),
+        threshold
+    );
+
+    let mut out = utils::open_output(args.output.as_deref())?;
+    for &rep in &clustering.representatives {
+        writeln!(out, "{}", paths[matrix.names[rep].as_str()])?;
+    }
+    out.flush()?;
+
+    if let Some(path) = &args.clusters {
+        let mut out = utils::open_output(Some(path))?;
+        cluster::write_clusters(&mut out, &matrix, &clustering)?;
+        out.flush()?;
+    }
+
+    if let Some(path) = &args.tree {
+        utils::check_input_count(clustering.n_clusters(), Operation::Tree)
+            .context("Too few representatives are left to build their tree")?;
+        let reps = dist::submatrix(&matrix, &clustering.representatives);
+        let tree = utils::compute_tree(&reps, args.canonical, args.threads)?;
+        utils::output_tree(Some(path.clone()), tree.to_newick(&Default::default()))?;
+    }
+
+    Ok(())
+}
+
+/// Read the query sketches, or sketch the query sequences with the
+/// parameters (and alphabet) of the reference sketches
+fn sketch_like(
+    inputs: &[String],
+    references: &[Sketch],
+    oversketch: usize,
+) -> anyhow::Result<Vec<Sketch>> {
+    if inputs.iter().all(|f| sketch::is_sketch_file(f)) {
+        let queries = sketch::read_sketches(inputs)?;
+        protein::check_alphabets(references.iter().chain(&queries))?;
+        return Ok(queries);
+    }
+    let sketch_params = [sketch::params_like(references, oversketch)?];
+    let amino_acids = references.first().is_some_and(protein::is_protein_sketch);
+    let scans: Vec<sketch::Scan> = inputs
+        .par_iter()
+        .map(|f| {
+            if amino_acids {
+                protein::scan_file(f, &sketch_params, &Default::default())
+            } else {
+                sketch::scan_file(f, &sketch_params, &Default::default())
+            }
+        })
+        .collect::<anyhow::Result<_>>()?;
+    utils::check_sequences(inputs.iter().map(String::as_str).zip(&scans))?;
+    Ok(scans.into_iter().flat_map(|scan| scan.sketches).collect())
+}
+
+/// Stream the distances between query and reference genomes
+fn dist(args: &cli::DistArgs) -> anyhow::Result<()> {
+    init_thread_pool(args.threads)?;
+    let query_files: Vec<inputs::InputFile> =
+        args.query.iter().map(inputs::InputFile::new).collect();
+    inputs::check_unique_names(&query_files)?;
+    let reference_files = collect_inputs(&args.reference, &args.source)?;
+    let query_paths: Vec<String> = query_files.iter().map(|g| g.path.clone()).collect();
+    let reference_paths: Vec<String> = reference_files.iter().map(|g| g.path.clone()).collect();
+
+    let (queries, references) = if reference_paths.iter().all(|f| sketch::is_sketch_file(f)) {
+        let references = sketch::read_sketches(&reference_paths)?;
+        let queries = sketch_like(&query_paths, &references, args.sketch.oversketch)?;
+        (queries, references)
+    } else {
+        // Sketch both sets together so that they share the same k
+        let inputs: Vec<String> = query_paths
+            .iter()
+            .chain(&reference_paths)
+            .cloned()
+            .collect();
+        let (_, mut sketches) = sketch_sequences(&inputs, &args.sketch)?;
+        let references = sketches.split_off(query_paths.len());
+        (sketches, references)
+    };
+    let labels = inputs::labels(&reference_files);
+
+    let mut out = utils::open_output(args.output.as_deref())?;
+    dist::write_hits_header(&mut out)?;
+    for query in &queries {
+        let hits = dist::query_hits(query, &references, args.max_dist, args.top);
+        dist::write_hits(&mut out, &hits, &labels)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Sketch genomes into one sketch file per input, or into a single stream
+/// on stdout
+fn sketch_genomes(args: &cli::SketchCommandArgs) -> anyhow::Result<()> {
+    init_thread_pool(args.threads)?;
+    let genomes = collect_inputs(&args.input, &args.source)?;
+    let filenames: Vec<String> = genomes.iter().map(|g| g.path.clone()).collect();
+    utils::check_input_count(filenames.len(), Operation::Sketch)?;
+
+    let pipeline = pipeline_for(&filenames, &args.sketch).no_tree();
+    // Statistics and one sketch per k-mer size of each file, or one sketch
+    // per record
+    let scan_all = |kmer_sizes: &[u8]| -> anyhow::Result<Vec<(utils::AssemblyStats, Vec<Sketch>)>> {
+        let params: Vec<SketchParams> = kmer_sizes
+            .iter()
+            .map(|&k| pipeline.config().sketch.params(k))
+            .collect();
+        let bar = progress::bar(filenames.len() as u64, "Sketching");
+        let scans = filenames
+            .par_iter()
+            .map(|f| {
+                if args.per_record {
+                    return Ok((Default::default(), sketch::sketch_records(f, &params[0])?));
+                }
+                let scan = if args.sketch.protein {
+                    protein::scan_file(f, &params, &Default::default())?
+                } else {
+                    sketch::scan_file(f, &params, &Default::default())?
+                };
+                let scan = scan.checked(f)?;
+                Ok((scan.stats, scan.sketches))
+            })
+            .inspect(|_| bar.inc(1))
+            .collect::<anyhow::Result<_>>();
+        bar.finish_and_clear();
+        scans
+    };
+
+    let (k, scans) = match args.sketch.kmer.as_slice() {
+        [k] => (*k, scan_all(&[*k])?),
+        // Sketch candidate sizes while reading the genome sizes
+        [] if pipeline.computes_kmer_size() && !args.per_record => {
+            let candidates = pipeline.candidate_kmer_sizes();
+            let mut scans = scan_all(&candidates)?;
+            let stats: Vec<utils::AssemblyStats> =
+                scans.iter().map(|(stats, _)| stats.clone()).collect();
+            pipeline.check_size_outliers(&stats)?;
+            let k = pipeline.kmer_sizes_for(&stats)[0];
+            log::info!("Computed k-mer size: {}", k);
+            match candidates.iter().position(|&c| c == k) {
+                Some(i) => {
+                    for (_, sketches) in &mut scans {
+                        *sketches = vec![sketches.swap_remove(i)];
+                    }
+                }
+                None => scans = scan_all(&[k])?,
+            }
+            (k, scans)
+        }
+        [] => {
+            let stats = match args.sketch.genome_size {
+                Some(_) => Vec::new(),
+                None => pipeline.genome_stats()?,
+            };
+            if pipeline.computes_kmer_size() {
+                pipeline.check_size_outliers(&stats)?;
+            }
+            let k = pipeline.kmer_sizes_for(&stats)[0];
+            log::info!("Computed k-mer size: {}", k);
+            (k, scan_all(&[k])?)
+        }
+        _ => {
+            return Err(Error::Input(
+                "Several k-mer sizes are only supported when building a tree".to_string(),
+            )
+            .into())
+        }
+    };
+    let params = pipeline.config().sketch.params(k);
+    let table: Vec<GenomeStats> = genomes
+        .iter()
+        .zip(&scans)
+        .map(|(genome, (stats, sketches))| {
+            GenomeStats::new(genome.name(), stats.clone(), sketches.first())
+        })
         .collect();
+    write_stats_table(&args.stats, &table)?;
+    let per_file: Vec<Vec<Sketch>> = scans.into_iter().map(|(_, sketches)| sketches).collect();
 
-    // Step 2: Compute distance between sketches
-    let sketch_distance = dist::compute_distances(sketches);
+    if args.stdout {
+        let sketches: Vec<Sketch> = per_file.into_iter().flatten().collect();
+        let mut out = io::BufWriter::new(io::stdout().lock());
+        sketch::write_sketch_stream(&mut out, &sketches, args.format)?;
+        out.flush()?;
+    } else {
+        fs::create_dir_all(&args.outdir).map_err(|e| Error::io(&args.outdir, e))?;
+        for (filename, sketches) in filenames.iter().zip(&per_file) {
+            sketch::save_sketches(filename, sketches, &params, &args.outdir).map_err(|e| {
+                Error::Sketch(format!("Could not save the sketch of {}: {}", filename, e))
+            })?;
+        }
+    }
+    Ok(())
+}
 
-    // 2.1. Compute matrix
-    let matrix = dist::distance_to_matrix(sketch_distance);
+/// Report the assembly statistics and distinct k-mer estimate of each genome
+fn info(args: &cli::InfoArgs) -> anyhow::Result<()> {
+    init_thread_pool(args.threads)?;
+    let genomes = collect_inputs(&args.input, &args.source)?;
+    let filenames: Vec<String> = genomes.iter().map(|g| g.path.clone()).collect();
+    utils::check_input_count(filenames.len(), Operation::Sketch)?;
+    // Outliers are reported, k is only used to estimate distinct k-mers
+    let sketched = pipeline_for(&filenames, &args.sketch).no_tree().survey()?;
 
-    // Step 3: Compute tree
-    // 3.1. Compute tree;
-    let newick: String = utils::compute_newick_tree(&matrix, cli.canonical, cli.threads)?;
+    let (_, sketches) = &sketched.per_k[0];
+    let mut table: Vec<GenomeStats> = genomes
+        .iter()
+        .zip(sketched.stats)
+        .zip(sketches)
+        .map(|((genome, stats), sketch)| GenomeStats::new(genome.name(), stats, Some(sketch)))
+        .collect();
+    stats::mark_outliers(&mut table, &sketched.outliers);
+    let mut out = utils::open_output(args.output.as_deref())?;
+    stats::write_stats(&mut out, &table, args.format)?;
+    out.flush()?;
+    Ok(())
+}
 
-    // 3.2. Output tree
-    utils::output_tree(cli.output, newick)?;
+/// Find the nearest references of each query through a hash index
+fn search(args: &cli::SearchArgs) -> anyhow::Result<()> {
+    init_thread_pool(args.threads)?;
 
-    // Manage tempdir and tempfiles
-    utils::manage_tempdir(cli.keep, &matrix, tempdir)?;
+    let index_path = args
+        .index
+        .clone()
+        .or_else(|| match args.reference.as_slice() {
+            [reference] => Some(format!("{}.idx", reference)),
+            _ => None,
+        });
+    let index = match index_path {
+        Some(path) => open_index(&path, &args.reference)?,
+        None => index::SketchIndex::new(sketch::read_sketches(&args.reference)?),
+    }
+    .max_candidates(args.candidates);
+    let queries = sketch_like(&args.input, index.sketches(), args.oversketch)?;
+
+    let mut out = utils::open_output(args.output.as_deref())?;
+    dist::write_hits_header(&mut out)?;
+    for query in &queries {
+        dist::write_hits(&mut out, &index.nearest(query, args.top), &HashMap::new())?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Read the index of the `references` from `path`, or build it and write it
+/// there if it is missing or was made for other references
+fn open_index(path: &str, references: &[String]) -> anyhow::Result<index::SketchIndex> {
+    let sketches = sketch::read_sketches(references)?;
+    let sketches = match fs::File::open(path) {
+        Ok(file) => match index::SketchIndex::read(&mut io::BufReader::new(file), sketches) {
+            Ok(index) => {
+                log::info!("Read the index of {} references from {}", index.len(), path);
+                return Ok(index);
+            }
+            Err(err) => {
+                log::warn!("Rebuilding the index {}: {:#}", path, err);
+                sketch::read_sketches(references)?
+            }
+        },
+        Err(_) => sketches,
+    };
+
+    let index = index::SketchIndex::new(sketches);
+    // Written aside and renamed, so that an interrupted run leaves no
+    // truncated index behind
+    let partial = format!("{}.tmp", path);
+    let written = fs::File::create(&partial).and_then(|file| {
+        let mut out = io::BufWriter::new(file);
+        index.write(&mut out)?;
+        out.flush()?;
+        fs::rename(&partial, path)
+    });
+    match written {
+        Ok(()) => log::info!("Wrote the index of {} references to {}", index.len(), path),
+        Err(err) => {
+            let _ = fs::remove_file(&partial);
+            log::warn!("Could not write the index {}: {}", path, err);
+        }
+    }
+    Ok(index)
+}
+
+/// Place query genomes into an existing tree
+fn place(args: &cli::PlaceArgs) -> anyhow::Result<()> {
+    init_thread_pool(args.threads)?;
+
+    let mut tree = utils::read_tree(&args.tree)?;
+    let references = sketch::read_sketches(&args.reference)?;
+    let queries = sketch_like(&args.input, &references, args.oversketch)?;
+
+    let mut distances: HashMap<String, HashMap<String, f64>> = HashMap::new();
+    for d in dist::compute_query_distances(&queries, &references) {
+        distances
+            .entry(dist::sketch_name(&d.query))
+            .or_default()
+            .insert(dist::sketch_name(&d.reference), d.mash_distance);
+    }
+    let mut names: Vec<&String> = distances.keys().collect();
+    names.sort();
 
+    let placements: Vec<place::Placement> = names
+        .par_iter()
+        .map(|name| place::place_query(&tree, name, &distances[*name], args.weighting))
+        .collect::<anyhow::Result<_>>()?;
+
+    let mut report: Box<dyn Write> = match &args.report {
+        Some(path) => utils::open_output(Some(path))?,
+        None => Box::new(io::stderr()),
+    };
+    place::write_placements(&mut report, &tree, &placements)?;
+    report.flush()?;
+
+    place::insert_placements(&mut tree, &placements);
+    utils::output_tree(args.output.clone(), tree.to_newick(&Default::default()))
+}
+
+/// Draw a Newick tree in the terminal
+fn view(args: &cli::ViewArgs) -> anyhow::Result<()> {
+    let tree = utils::read_tree(&args.tree)?;
+    let options = ascii::AsciiOptions {
+        width: args.width.unwrap_or_else(ascii::terminal_width),
+        unicode: !args.ascii,
+        support: args.support,
+    };
+    print!("{}", ascii::render_ascii(&tree, &options));
     Ok(())
 }
+
+/// Draw a Newick tree as SVG
+fn render(args: &cli::RenderArgs) -> anyhow::Result<()> {
+    let tree = utils::read_tree(&args.tree)?;
+    let options = svg_options(&args.svg)?;
+    utils::output_tree(args.output.clone(), svg::render_svg(&tree, &options))
+}
+
+fn svg_options(args: &cli::SvgArgs) -> anyhow::Result<svg::SvgOptions> {
+    let colors = match (&args.metadata, &args.color_by) {
+        (Some(path), Some(column)) => svg::read_leaf_colors(path, column)?,
+        _ => svg::LeafColors::default(),
+    };
+    Ok(svg::SvgOptions {
+        layout: args.layout,
+        width: args.svg_width,
+        support: args.show_support,
+        colors,
+    })
+}
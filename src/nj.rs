@@ -0,0 +1,289 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+//! Neighbor-joining that reports each join, so that long tree builds can
+//! show their progress.
+//!
+//! The canonical method scans the whole Q-matrix at each step. The rapid
+//! method keeps every row sorted by distance and stops scanning a row once
+//! no later entry can beat the best pair, as in RapidNJ (Simonsen et al.,
+//! 2008). Both return the same unrooted graph as [`speedytree`].
+
+use rayon::prelude::*;
+use speedytree::DistanceMatrix;
+
+use crate::error::Error;
+
+/// Q value, row and column of a pair of nodes
+type Pair = (f64, usize, usize);
+
+const NO_PAIR: Pair = (f64::INFINITY, usize::MAX, usize::MAX);
+
+struct Joiner {
+    dist: Vec<Vec<f64>>,
+    sums: Vec<f64>,
+    active: Vec<bool>,
+    /// Rows sorted by distance (rapid method only), with the generation of
+    /// each column when the row was sorted
+    sorted: Vec<Vec<(f64, usize, u32)>>,
+    /// Number of joins that reused each row
+    generation: Vec<u32>,
+    remaining: usize,
+}
+
+impl Joiner {
+    fn new(matrix: &DistanceMatrix, rapid: bool) -> Self {
+        let n = matrix.size();
+        let dist = matrix.matrix.clone();
+        let sums = dist.iter().map(|row| row.iter().sum()).collect();
+        let active = vec![true; n];
+        let generation = vec![0; n];
+        let sorted = if rapid {
+            (0..n)
+                .into_par_iter()
+                .map(|i| sorted_row(&dist[i], i, &active, &generation))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Self {
+            dist,
+            sums,
+            active,
+            sorted,
+            generation,
+            remaining: n,
+        }
+    }
+
+    /// Pair of nodes with the smallest Q value
+    fn closest_pair(&self) -> (usize, usize) {
+        let scale = (self.remaining - 2) as f64;
+        let max_sum = (0..self.dist.len())
+            .filter(|&i| self.active[i])
+            .map(|i| self.sums[i])
+            .fold(f64::NEG_INFINITY, f64::max);
+
+        let (_, i, j) = (0..self.dist.len())
+            .into_par_iter()
+            .filter(|&i| self.active[i])
+            .map(|i| {
+                if self.sorted.is_empty() {
+                    self.scan_row(i, scale)
+                } else {
+                    self.scan_sorted_row(i, scale, max_sum)
+                }
+            })
+            .reduce(|| NO_PAIR, min_pair);
+        (i, j)
+    }
+
+    fn scan_row(&self, i: usize, scale: f64) -> Pair {
+        (i + 1..self.dist.len())
+            .filter(|&j| self.active[j])
+            .map(|j| (self.dist[i][j] * scale - self.sums[i] - self.sums[j], i, j))
+            .fold(NO_PAIR, min_pair)
+    }
+
+    fn scan_sorted_row(&self, i: usize, scale: f64, max_sum: f64) -> Pair {
+        let mut best = NO_PAIR;
+        for &(d, j, generation) in &self.sorted[i] {
+            if d * scale - self.sums[i] - max_sum > best.0 {
+                break;
+            }
+            if self.active[j] && self.generation[j] == generation {
+                let q = d * scale - self.sums[i] - self.sums[j];
+                best = min_pair(best, (q, i.min(j), i.max(j)));
+            }
+        }
+        best
+    }
+
+    /// Replace `i` and `j` by their parent, kept in row `i`, and return the
+    /// branch lengths from the parent to `i` and `j`
+    fn join(&mut self, i: usize, j: usize) -> (f64, f64) {
+        let scale = (self.remaining - 2) as f64;
+        let dij = self.dist[i][j];
+        let to_i = (dij + (self.sums[i] - self.sums[j]) / scale) / 2.0;
+
+        self.active[j] = false;
+        self.remaining -= 1;
+        let mut sum = 0.0;
+        for k in 0..self.dist.len() {
+            if !self.active[k] || k == i {
+                continue;
+            }
+            let d = (self.dist[i][k] + self.dist[j][k] - dij) / 2.0;
+            self.sums[k] += d - self.dist[i][k] - self.dist[j][k];
+            self.dist[i][k] = d;
+            self.dist[k][i] = d;
+            sum += d;
+        }
+        self.sums[i] = sum;
+
+        if !self.sorted.is_empty() {
+            self.generation[i] += 1;
+            self.sorted[i] = sorted_row(&self.dist[i], i, &self.active, &self.generation);
+            self.sorted[j] = Vec::new();
+        }
+        (to_i, dij - to_i)
+    }
+}
+
+fn sorted_row(
+    row: &[f64],
+    i: usize,
+    active: &[bool],
+    generation: &[u32],
+) -> Vec<(f64, usize, u32)> {
+    let mut sorted: Vec<_> = row
+        .iter()
+        .enumerate()
+        .filter(|&(j, _)| j != i && active[j])
+        .map(|(j, &d)| (d, j, generation[j]))
+        .collect();
+    sorted.sort_unstable_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+    sorted
+}
+
+/// Pair with the smallest Q value, ties going to the first one in matrix
+/// order
+fn min_pair(a: Pair, b: Pair) -> Pair {
+    if b.0 < a.0 || (b.0 == a.0 && (b.1, b.2) < (a.1, a.2)) {
+        b
+    } else {
+        a
+    }
+}
+
+/// Build the neighbor-joining tree of `matrix`, calling `on_join` after each
+/// of its `n - 3` joins
+pub fn neighbor_joining(
+    matrix: &DistanceMatrix,
+    rapid: bool,
+    mut on_join: impl FnMut(),
+) -> anyhow::Result<speedytree::Tree> {
+    let n = matrix.size();
+    if n < 3 {
+        return Err(Error::Tree("Neighbor-joining needs at least three taxa".to_string()).into());
+    }
+
+    let mut graph = speedytree::Tree::default();
+    let mut nodes: Vec<_> = matrix
+        .names
+        .iter()
+        .map(|name| graph.add_node(name.clone()))
+        .collect();
+    let mut joiner = Joiner::new(matrix, rapid);
+    while joiner.remaining > 3 {
+        let (i, j) = joiner.closest_pair();
+        let (to_i, to_j) = joiner.join(i, j);
+        let parent = graph.add_node(String::new());
+        graph.add_edge(parent, nodes[i], to_i);
+        graph.add_edge(parent, nodes[j], to_j);
+        nodes[i] = parent;
+        on_join();
+    }
+
+    let last: Vec<usize> = (0..n).filter(|&i| joiner.active[i]).collect();
+    let d = |a: usize, b: usize| joiner.dist[last[a]][last[b]];
+    let center = graph.add_node(String::new());
+    graph.add_edge(center, nodes[last[0]], (d(0, 1) + d(0, 2) - d(1, 2)) / 2.0);
+    graph.add_edge(center, nodes[last[1]], (d(0, 1) + d(1, 2) - d(0, 2)) / 2.0);
+    graph.add_edge(center, nodes[last[2]], (d(0, 2) + d(1, 2) - d(0, 1)) / 2.0);
+    Ok(graph)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::{robinson_foulds, Tree};
+
+    fn sorted_lengths(graph: &speedytree::Tree) -> Vec<f64> {
+        let mut lengths: Vec<f64> = graph.edge_weights().copied().collect();
+        lengths.sort_by(f64::total_cmp);
+        lengths
+    }
+
+    /// Noisy distances between `n` taxa
+    fn random_matrix(n: usize) -> DistanceMatrix {
+        let mut state = 42u64;
+        let mut next = || {
+            state = state
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+        let noise: Vec<Vec<f64>> = (0..n)
+            .map(|i| (0..i).map(|_| next() / 10.0).collect())
+            .collect();
+        let matrix = (0..n)
+            .map(|i| {
+                (0..n)
+                    .map(|j| match i.cmp(&j) {
+                        std::cmp::Ordering::Equal => 0.0,
+                        std::cmp::Ordering::Greater => (i - j) as f64 / n as f64 + noise[i][j],
+                        std::cmp::Ordering::Less => (j - i) as f64 / n as f64 + noise[j][i],
+                    })
+                    .collect()
+            })
+            .collect();
+        DistanceMatrix {
+            matrix,
+            names: (0..n).map(|i| format!("g{}", i)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_neighbor_joining() {
+        let matrix = DistanceMatrix {
+            matrix: vec![
+                vec![0.0, 5.0, 9.0, 9.0, 8.0],
+                vec![5.0, 0.0, 10.0, 10.0, 9.0],
+                vec![9.0, 10.0, 0.0, 8.0, 7.0],
+                vec![9.0, 10.0, 8.0, 0.0, 3.0],
+                vec![8.0, 9.0, 7.0, 3.0, 0.0],
+            ],
+            names: ["a", "b", "c", "d", "e"].map(String::from).to_vec(),
+        };
+        let expected = Tree::from_newick("((a:2,b:3):3,c:4,(d:2,e:1):2);").unwrap();
+        for rapid in [false, true] {
+            let mut joins = 0;
+            let graph = neighbor_joining(&matrix, rapid, || joins += 1).unwrap();
+            assert_eq!(joins, 2);
+            let tree = Tree::from_speedytree(&graph).unwrap();
+            assert_eq!(robinson_foulds(&tree, &expected).unwrap().0, 0);
+            assert_eq!(sorted_lengths(&graph), [1.0, 2.0, 2.0, 2.0, 3.0, 3.0, 4.0]);
+        }
+
+        let pair = DistanceMatrix {
+            matrix: vec![vec![0.0, 1.0], vec![1.0, 0.0]],
+            names: ["a", "b"].map(String::from).to_vec(),
+        };
+        assert!(neighbor_joining(&pair, false, || ()).is_err());
+    }
+
+    #[test]
+    fn test_same_tree_as_speedytree() {
+        let matrix = random_matrix(40);
+        let graph =
+            speedytree::NeighborJoiningSolver::<speedytree::Canonical>::default(matrix.clone())
+                .solve()
+                .unwrap();
+        let expected = Tree::from_speedytree(&graph).unwrap();
+        let expected_lengths = sorted_lengths(&graph);
+
+        for rapid in [false, true] {
+            let mut joins = 0;
+            let graph = neighbor_joining(&matrix, rapid, || joins += 1).unwrap();
+            assert_eq!(joins, 37);
+            let tree = Tree::from_speedytree(&graph).unwrap();
+            assert_eq!(robinson_foulds(&tree, &expected).unwrap().0, 0);
+            for (a, b) in sorted_lengths(&graph).iter().zip(&expected_lengths) {
+                assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+            }
+        }
+    }
+}
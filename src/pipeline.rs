@@ -0,0 +1,1022 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::collections::{HashMap, HashSet};
+
+use anyhow::Context;
+use finch::{serialization::Sketch, sketch_schemes::SketchParams};
+use itertools::Itertools;
+use rayon::prelude::*;
+use serde_json::{json, Value};
+use speedytree::DistanceMatrix;
+
+use crate::error::{Error, Failure};
+use crate::filter::{FilterDecision, RecordFilter};
+use crate::report::Timings;
+use crate::stats::{self, GenomeStats};
+use crate::tree::{NegativeBranchSummary, NegativeBranches, Tree};
+use crate::utils::{AssemblyStats, Operation, SeqStats};
+use crate::{dist, inputs, progress, protein, sketch, units, utils};
+
+/// Neighbor-joining variant used to build trees
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TreeMethod {
+    /// Rapid neighbor-joining
+    #[default]
+    Rapid,
+    /// Canonical neighbor-joining
+    Canonical,
+}
+
+/// What to do with inputs that cannot be read or are not usable genomes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum OnError {
+    /// Fail the whole run
+    #[default]
+    Abort,
+    /// Leave the genome out and report it as a failure
+    Skip,
+}
+
+/// Sketching parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct SketchConfig {
+    /// K-mer sizes; computed from the mean genome size when empty
+    pub kmer_sizes: Vec<u8>,
+    /// Number of hashes of Mash sketches
+    pub size: usize,
+    pub seed: u64,
+    /// Amount of extra sketching before filtering
+    pub oversketch: usize,
+    /// Keep one hash out of `scaled` (FracMinHash) instead of `size` hashes
+    pub scaled: Option<u64>,
+    /// Sketch amino-acid k-mers
+    pub protein: bool,
+    /// Genome size used to compute k instead of the mean genome size
+    pub genome_size: Option<usize>,
+}
+
+impl Default for SketchConfig {
+    fn default() -> Self {
+        SketchConfig {
+            kmer_sizes: Vec::new(),
+            size: 1000,
+            seed: 42,
+            oversketch: 200,
+            scaled: None,
+            protein: false,
+            genome_size: None,
+        }
+    }
+}
+
+impl SketchConfig {
+    /// Finch parameters for k-mer size `k`
+    pub fn params(&self, k: u8) -> SketchParams {
+        match self.scaled {
+            Some(scaled) => sketch::scaled_params(k, scaled, self.seed),
+            None => sketch::mash_params(k, self.size, self.oversketch, self.seed),
+        }
+    }
+}
+
+/// Input filtering parameters
+#[derive(Debug, Clone, PartialEq)]
+pub struct FilterConfig {
+    /// Relative impact on the mean genome size above which a genome is an
+    /// outlier, see [`utils::detect_outliers`]; `None` disables the check
+    pub outliers: Option<f64>,
+    /// Genomes shorter than this are left out of the analysis
+    pub min_genome_size: Option<usize>,
+    /// Genomes longer than this are left out of the analysis
+    pub max_genome_size: Option<usize>,
+    /// Genomes with a larger fraction of N bases are left out of the analysis
+    pub max_n_fraction: Option<f64>,
+    /// Contigs shorter than this are dropped before sketching
+    pub min_contig_length: Option<usize>,
+    /// Mask low-complexity regions before sketching
+    pub mask_low_complexity: bool,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig {
+            outliers: Some(0.05),
+            min_genome_size: None,
+            max_genome_size: None,
+            max_n_fraction: None,
+            min_contig_length: None,
+            mask_low_complexity: false,
+        }
+    }
+}
+
+impl FilterConfig {
+    /// Filters applied to the records of each genome
+    pub fn record_filter(&self) -> RecordFilter {
+        RecordFilter {
+            min_length: self.min_contig_length,
+            mask_low_complexity: self.mask_low_complexity,
+        }
+    }
+
+    /// Why a genome with `stats` is left out of the analysis, if it is;
+    /// the N fraction is not checked for amino-acid genomes
+    pub fn exclusion(&self, stats: &AssemblyStats) -> Option<String> {
+        let mut reasons = Vec::new();
+        if let Some(min) = self.min_genome_size.filter(|&min| stats.length < min) {
+            reasons.push(format!(
+                "genome size {} is below the minimum of {}",
+                units::format_size(stats.length, 1),
+                units::format_size(min, 1)
+            ));
+        }
+        if let Some(max) = self.max_genome_size.filter(|&max| stats.length > max) {
+            reasons.push(format!(
+                "genome size {} is above the maximum of {}",
+                units::format_size(stats.length, 1),
+                units::format_size(max, 1)
+            ));
+        }
+        let max_n_fraction = self.max_n_fraction.filter(|_| !stats.amino_acids);
+        if let Some(max) = max_n_fraction.filter(|&max| stats.n_fraction() > max) {
+            reasons.push(format!(
+                "N fraction {:.4} is above the maximum of {}",
+                stats.n_fraction(),
+                max
+            ));
+        }
+        (!reasons.is_empty()).then(|| reasons.join("; "))
+    }
+}
+
+/// Tree building parameters
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TreeConfig {
+    pub method: TreeMethod,
+    pub negative_branches: NegativeBranches,
+}
+
+/// Full configuration of a [`Pipeline`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Single-sequence FASTA files, one per genome
+    pub inputs: Vec<String>,
+    pub sketch: SketchConfig,
+    pub filter: FilterConfig,
+    /// Tree building, `None` to stop at the distance matrix
+    pub tree: Option<TreeConfig>,
+    /// Threads used by the neighbor-joining solver
+    pub threads: usize,
+    /// Directory where the sketch of each genome is written
+    pub sketch_dir: Option<String>,
+    /// Names of genomes in matrices and trees, by file stem; unlabelled
+    /// genomes are named after their file stem
+    pub labels: HashMap<String, String>,
+    pub on_error: OnError,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            inputs: Vec::new(),
+            sketch: SketchConfig::default(),
+            filter: FilterConfig::default(),
+            tree: Some(TreeConfig::default()),
+            threads: 1,
+            sketch_dir: None,
+            labels: HashMap::new(),
+            on_error: OnError::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Parameters of a run as JSON, with the k-mer sizes actually used
+    pub fn to_json(&self, kmer_sizes: &[u8]) -> Value {
+        let sketch = &self.sketch;
+        let filter = &self.filter;
+        json!({
+            "inputs": self.inputs,
+            "kmer_sizes": kmer_sizes,
+            "kmer_sizes_computed": sketch.kmer_sizes.is_empty(),
+            "sketch_size": sketch.size,
+            "scaled": sketch.scaled,
+            "seed": sketch.seed,
+            "oversketch": sketch.oversketch,
+            "protein": sketch.protein,
+            "genome_size": sketch.genome_size,
+            "outlier_epsilon": filter.outliers,
+            "min_genome_size": filter.min_genome_size,
+            "max_genome_size": filter.max_genome_size,
+            "max_n_fraction": filter.max_n_fraction,
+            "min_contig_length": filter.min_contig_length,
+            "mask_low_complexity": filter.mask_low_complexity,
+            "tree_method": self.tree.as_ref().map(|tree| match tree.method {
+                TreeMethod::Rapid => "rapid",
+                TreeMethod::Canonical => "canonical",
+            }),
+            "negative_branches": self.tree.as_ref().map(|tree| value_name(tree.negative_branches)),
+            "on_error": value_name(self.on_error),
+            "threads": self.threads,
+        })
+    }
+}
+
+/// Name of `value` on the command line
+fn value_name(value: impl clap::ValueEnum) -> Option<String> {
+    value
+        .to_possible_value()
+        .map(|value| value.get_name().to_string())
+}
+
+/// Sketches of every genome, for each k-mer size
+pub type SketchesPerK = Vec<(u8, Vec<Sketch>)>;
+
+/// Genomes kept after filtering, with their statistics and sketches
+#[derive(Debug, Clone)]
+pub struct Sketched {
+    /// Kept inputs, in input order
+    pub inputs: Vec<String>,
+    pub stats: Vec<AssemblyStats>,
+    pub per_k: SketchesPerK,
+    /// Filter decision of every readable input, in input order
+    pub filters: Vec<FilterDecision>,
+    /// Inputs skipped because they could not be used, see [`OnError::Skip`]
+    pub failures: Vec<Failure>,
+    /// Genome size outliers among the kept genomes; they only fail the run
+    /// when k is computed from the mean genome size of Mash sketches
+    pub outliers: Vec<SeqStats>,
+}
+
+/// Results obtained at one k-mer size
+#[derive(Debug, Clone)]
+pub struct KmerRun {
+    pub k: u8,
+    /// Sketches in input order
+    pub sketches: Vec<Sketch>,
+    pub matrix: DistanceMatrix,
+    pub tree: Option<Tree>,
+    /// Changes made to negative branch lengths of `tree`
+    pub negative_branches: NegativeBranchSummary,
+}
+
+/// Structured results of a [`Pipeline`] run
+#[derive(Debug, Clone)]
+pub struct PipelineResult {
+    /// Name of each kept genome in outputs, labels applied, in input order
+    pub names: Vec<String>,
+    /// Assembly statistics of each genome, in input order
+    pub stats: Vec<AssemblyStats>,
+    /// One run per k-mer size, the first one being the main result
+    pub runs: Vec<KmerRun>,
+    /// Filter decision of every readable input, in input order
+    pub filters: Vec<FilterDecision>,
+    /// Inputs skipped because they could not be used
+    pub failures: Vec<Failure>,
+    pub outliers: Vec<SeqStats>,
+    /// Time spent sketching, computing distances and building trees
+    pub timings: Timings,
+}
+
+impl PipelineResult {
+    pub fn main(&self) -> &KmerRun {
+        &self.runs[0]
+    }
+
+    pub fn sketches(&self) -> &[Sketch] {
+        &self.main().sketches
+    }
+
+    pub fn matrix(&self) -> &DistanceMatrix {
+        &self.main().matrix
+    }
+
+    pub fn tree(&self) -> Option<&Tree> {
+        self.main().tree.as_ref()
+    }
+
+    /// Statistics table of the genomes, in input order, with the distinct
+    /// k-mers estimated from the main sketches and genome size outliers
+    pub fn genome_stats(&self) -> Vec<GenomeStats> {
+        let mut genomes: Vec<GenomeStats> = self
+            .names
+            .iter()
+            .zip(&self.stats)
+            .zip(self.sketches())
+            .map(|((name, stats), sketch)| GenomeStats::new(name, stats.clone(), Some(sketch)))
+            .collect();
+        stats::mark_outliers(&mut genomes, &self.outliers);
+        genomes
+    }
+
+    /// Inputs of the genome size outliers, as named in the filter decisions
+    pub fn outlier_inputs(&self) -> HashSet<&str> {
+        self.filters
+            .iter()
+            .filter(|decision| decision.excluded.is_none())
+            .zip(&self.stats)
+            .filter(|(_, stats)| self.outliers.contains(&stats.seq_stats()))
+            .map(|(decision, _)| decision.input.as_str())
+            .collect()
+    }
+}
+
+/// Genomes to tree pipeline: statistics, sketches, distance matrix and
+/// neighbor-joining tree.
+///
+/// ```no_run
+/// use cedar::pipeline::{Pipeline, TreeMethod};
+///
+/// let result = Pipeline::new(["a.fna", "b.fna", "c.fna"])
+///     .kmer_sizes([21])
+///     .tree_method(TreeMethod::Canonical)
+///     .run()?;
+/// println!("{}", result.tree().unwrap().to_newick(&Default::default()));
+/// # Ok::<(), anyhow::Error>(())
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Pipeline {
+    config: Config,
+}
+
+impl Pipeline {
+    /// Pipeline with the default configuration
+    pub fn new<I, S>(inputs: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Pipeline::with_config(Config {
+            inputs: inputs.into_iter().map(Into::into).collect(),
+            ..Default::default()
+        })
+    }
+
+    pub fn with_config(config: Config) -> Self {
+        Pipeline { config }
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn kmer_sizes(mut self, kmer_sizes: impl Into<Vec<u8>>) -> Self {
+        self.config.sketch.kmer_sizes = kmer_sizes.into();
+        self
+    }
+
+    pub fn sketch_size(mut self, size: usize) -> Self {
+        self.config.sketch.size = size;
+        self
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.sketch.seed = seed;
+        self
+    }
+
+    pub fn oversketch(mut self, oversketch: usize) -> Self {
+        self.config.sketch.oversketch = oversketch;
+        self
+    }
+
+    pub fn scaled(mut self, scaled: Option<u64>) -> Self {
+        self.config.sketch.scaled = scaled;
+        self
+    }
+
+    pub fn protein(mut self, protein: bool) -> Self {
+        self.config.sketch.protein = protein;
+        self
+    }
+
+    pub fn genome_size(mut self, size: Option<usize>) -> Self {
+        self.config.sketch.genome_size = size;
+        self
+    }
+
+    pub fn outliers(mut self, epsilon: Option<f64>) -> Self {
+        self.config.filter.outliers = epsilon;
+        self
+    }
+
+    pub fn min_genome_size(mut self, size: Option<usize>) -> Self {
+        self.config.filter.min_genome_size = size;
+        self
+    }
+
+    pub fn max_genome_size(mut self, size: Option<usize>) -> Self {
+        self.config.filter.max_genome_size = size;
+        self
+    }
+
+    pub fn max_n_fraction(mut self, fraction: Option<f64>) -> Self {
+        self.config.filter.max_n_fraction = fraction;
+        self
+    }
+
+    pub fn min_contig_length(mut self, length: Option<usize>) -> Self {
+        self.config.filter.min_contig_length = length;
+        self
+    }
+
+    pub fn mask_low_complexity(mut self, mask: bool) -> Self {
+        self.config.filter.mask_low_complexity = mask;
+        self
+    }
+
+    pub fn tree_method(mut self, method: TreeMethod) -> Self {
+        self.config.tree.get_or_insert_with(Default::default).method = method;
+        self
+    }
+
+    pub fn negative_branches(mut self, policy: NegativeBranches) -> Self {
+        self.config
+            .tree
+            .get_or_insert_with(Default::default)
+            .negative_branches = policy;
+        self
+    }
+
+    /// Stop at the distance matrix
+    pub fn no_tree(mut self) -> Self {
+        self.config.tree = None;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.config.threads = threads.max(1);
+        self
+    }
+
+    pub fn sketch_dir(mut self, dir: impl Into<String>) -> Self {
+        self.config.sketch_dir = Some(dir.into());
+        self
+    }
+
+    pub fn labels(mut self, labels: HashMap<String, String>) -> Self {
+        self.config.labels = labels;
+        self
+    }
+
+    pub fn on_error(mut self, on_error: OnError) -> Self {
+        self.config.on_error = on_error;
+        self
+    }
+
+    /// Check the inputs, then sketch them and compute a distance matrix and
+    /// a tree for each k-mer size
+    pub fn run(&self) -> anyhow::Result<PipelineResult> {
+        let operation = self.operation();
+        utils::check_input_count(self.config.inputs.len(), operation)?;
+        let mut timings = Timings::default();
+        let sketched = timings.time("sketching", || self.stats_and_sketches())?;
+        log::info!(
+            "Sketched {} of {} genomes",
+            sketched.inputs.len(),
+            self.config.inputs.len()
+        );
+        if sketched.inputs.len() < self.config.inputs.len() {
+            utils::check_input_count(sketched.inputs.len(), operation)
+                .context("Too few genomes are left after filtering and skipping failures")?;
+        }
+        let runs = sketched
+            .per_k
+            .into_iter()
+            .map(|(k, sketches)| self.build(k, sketches, &mut timings))
+            .collect::<anyhow::Result<_>>()?;
+        let names = sketched
+            .inputs
+            .iter()
+            .map(|f| {
+                let name = dist::sketch_name(f);
+                self.config.labels.get(&name).cloned().unwrap_or(name)
+            })
+            .collect();
+        Ok(PipelineResult {
+            names,
+            stats: sketched.stats,
+            runs,
+            filters: sketched.filters,
+            failures: sketched.failures,
+            outliers: sketched.outliers,
+            timings,
+        })
+    }
+
+    /// Last step of [`Pipeline::run`], which sets the minimum number of genomes
+    fn operation(&self) -> Operation {
+        if self.config.tree.is_some() {
+            Operation::Tree
+        } else {
+            Operation::Distances
+        }
+    }
+
+    /// Validate and filter the inputs, then collect their statistics and
+    /// sketch them at each k-mer size, failing on genome size outliers when
+    /// k is computed from the mean genome size.
+    ///
+    /// Each file is read once. When k is computed, the genomes are sketched
+    /// at the candidate sizes of [`Pipeline::candidate_kmer_sizes`] while
+    /// their sizes are read; they are only read again in the rare case where
+    /// the computed k is not among them.
+    pub fn stats_and_sketches(&self) -> anyhow::Result<Sketched> {
+        self.read_once(true)
+    }
+
+    /// [`Pipeline::stats_and_sketches`] that only reports genome size
+    /// outliers, for statistics tables
+    pub fn survey(&self) -> anyhow::Result<Sketched> {
+        self.read_once(false)
+    }
+
+    fn read_once(&self, fail_on_outliers: bool) -> anyhow::Result<Sketched> {
+        if !self.computes_kmer_size() {
+            let mut sketched = self.scan(&self.kmer_sizes_for(&[]))?;
+            sketched.outliers = self.size_outliers(&sketched.stats)?;
+            self.save_sketches(&sketched)?;
+            return Ok(sketched);
+        }
+        let mut sketched = self.scan(&self.candidate_kmer_sizes())?;
+        let outliers = self.size_outliers(&sketched.stats)?;
+        if fail_on_outliers {
+            self.check_outliers(&outliers)?;
+        }
+        let kmer_sizes = self.kmer_sizes_for(&sketched.stats);
+        match sketched.per_k.iter().position(|(k, _)| *k == kmer_sizes[0]) {
+            Some(i) => sketched.per_k = vec![sketched.per_k.swap_remove(i)],
+            None => {
+                log::debug!(
+                    "Computed k-mer size {} is not among the sketched candidates, sketching again",
+                    kmer_sizes[0]
+                );
+                sketched = self.scan(&kmer_sizes)?;
+            }
+        }
+        sketched.outliers = outliers;
+        self.save_sketches(&sketched)?;
+        Ok(sketched)
+    }
+
+    /// k-mer sizes computed for the smallest and largest mean genome size
+    /// that the sizes of the input files allow, see
+    /// [`inputs::genome_size_bounds`]; empty if no file size is known
+    pub fn candidate_kmer_sizes(&self) -> Vec<u8> {
+        let bounds: Vec<(u64, u64)> = self
+            .config
+            .inputs
+            .iter()
+            .filter_map(|f| inputs::genome_size_bounds(f))
+            .collect();
+        if bounds.is_empty() {
+            return Vec::new();
+        }
+        let n = bounds.len() as u64;
+        let k = |total: u64| {
+            let mean = (total / n).max(1);
+            sketch::k_computing(u32::try_from(mean).unwrap_or(u32::MAX), 0.01)
+        };
+        let smallest = k(bounds.iter().map(|b| b.0).sum());
+        let largest = k(bounds.iter().map(|b| b.1).sum());
+        (smallest..=largest).collect()
+    }
+
+    /// Write the sketch of each kept genome to the sketch directory, if any
+    fn save_sketches(&self, sketched: &Sketched) -> anyhow::Result<()> {
+        let (Some(dir), Some((k, sketches))) = (&self.config.sketch_dir, sketched.per_k.first())
+        else {
+            return Ok(());
+        };
+        let params = self.config.sketch.params(*k);
+        for (filename, sketch) in sketched.inputs.iter().zip(sketches) {
+            sketch::save_sketches(filename, std::slice::from_ref(sketch), &params, dir).map_err(
+                |e| Error::Sketch(format!("Could not save the sketch of {}: {}", filename, e)),
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Whether k is computed from the mean genome size of the inputs
+    pub fn computes_kmer_size(&self) -> bool {
+        let sketch = &self.config.sketch;
+        sketch.kmer_sizes.is_empty() && !sketch.protein && sketch.genome_size.is_none()
+    }
+
+    /// Validate and filter the inputs and compute the assembly statistics of
+    /// each kept genome
+    pub fn genome_stats(&self) -> anyhow::Result<Vec<AssemblyStats>> {
+        Ok(self.scan(&[])?.stats)
+    }
+
+    /// Fail if genome size outliers among `stats` would bias the k-mer size
+    /// computed from their mean size
+    pub fn check_size_outliers(&self, stats: &[AssemblyStats]) -> anyhow::Result<()> {
+        self.check_outliers(&self.size_outliers(stats)?)
+    }
+
+    /// User-defined k-mer sizes, or the one computed from the genome size
+    /// (by default the mean genome size, [`protein::DEFAULT_KMER`] for amino
+    /// acids)
+    pub fn kmer_sizes_for(&self, stats: &[AssemblyStats]) -> Vec<u8> {
+        let sketch = &self.config.sketch;
+        if !sketch.kmer_sizes.is_empty() {
+            sketch.kmer_sizes.clone()
+        } else if sketch.protein {
+            vec![protein::DEFAULT_KMER]
+        } else {
+            let genome_size = match sketch.genome_size {
+                Some(size) => u32::try_from(size).unwrap_or(u32::MAX),
+                None => {
+                    let stats: Vec<SeqStats> = stats.iter().map(AssemblyStats::seq_stats).collect();
+                    utils::mean_genome_size(&stats)
+                }
+            };
+            vec![sketch::k_computing(genome_size, 0.01)]
+        }
+    }
+
+    /// Sketch every kept input at each k-mer size, reading each file once
+    pub fn sketch(&self, kmer_sizes: &[u8]) -> anyhow::Result<SketchesPerK> {
+        Ok(self.scan(kmer_sizes)?.per_k)
+    }
+
+    /// Read each input once to validate it, collect its statistics and
+    /// sketch it at each k-mer size, then leave out filtered genomes
+    fn scan(&self, kmer_sizes: &[u8]) -> anyhow::Result<Sketched> {
+        let config = &self.config;
+        let params: Vec<SketchParams> = kmer_sizes
+            .iter()
+            .map(|&k| config.sketch.params(k))
+            .collect();
+        let record_filter = config.filter.record_filter();
+        let bar = progress::bar(
+            config.inputs.len() as u64,
+            if kmer_sizes.is_empty() {
+                "Reading"
+            } else {
+                "Sketching"
+            },
+        );
+        let results: Vec<anyhow::Result<sketch::Scan>> = config
+            .inputs
+            .par_iter()
+            .map(|f| {
+                let scan = if config.sketch.protein {
+                    protein::scan_file(f, &params, &record_filter)
+                } else {
+                    sketch::scan_file(f, &params, &record_filter)
+                };
+                bar.inc(1);
+                scan
+            })
+            .collect();
+        bar.finish_and_clear();
+
+        let mut failures = Vec::new();
+        let scans: Vec<(&String, sketch::Scan)> = match config.on_error {
+            OnError::Abort => {
+                let scans: Vec<sketch::Scan> =
+                    results.into_iter().collect::<anyhow::Result<_>>()?;
+                utils::check_sequences(config.inputs.iter().map(String::as_str).zip(&scans))?;
+                config.inputs.iter().zip(scans).collect()
+            }
+            OnError::Skip => {
+                let mut scans = Vec::new();
+                for (filename, result) in config.inputs.iter().zip(results) {
+                    let reason = match result {
+                        Ok(scan) => match utils::sequence_problem(&scan) {
+                            Some(problem) => problem,
+                            None => {
+                                scans.push((filename, scan));
+                                continue;
+                            }
+                        },
+                        Err(err) => format!("{:#}", err),
+                    };
+                    failures.push(Failure {
+                        input: filename.clone(),
+                        reason,
+                    });
+                }
+                scans
+            }
+        };
+
+        let mut sketched = Sketched {
+            inputs: Vec::new(),
+            stats: Vec::new(),
+            per_k: kmer_sizes.iter().map(|&k| (k, Vec::new())).collect(),
+            filters: Vec::new(),
+            failures,
+            outliers: Vec::new(),
+        };
+        for (filename, scan) in scans {
+            let excluded = config.filter.exclusion(&scan.stats);
+            let kept = excluded.is_none();
+            sketched.filters.push(FilterDecision {
+                input: filename.clone(),
+                records: scan.filtered,
+                excluded,
+            });
+            if !kept {
+                continue;
+            }
+            sketched.inputs.push(filename.clone());
+            sketched.stats.push(scan.stats);
+            for ((_, column), sketch) in sketched.per_k.iter_mut().zip(scan.sketches) {
+                column.push(sketch);
+            }
+        }
+
+        if sketched.inputs.is_empty() {
+            let excluded = sketched
+                .filters
+                .iter()
+                .filter_map(|f| Some((&f.input, f.excluded.as_ref()?)));
+            let failed = sketched.failures.iter().map(|f| (&f.input, &f.reason));
+            if let Some((input, reason)) = failed.chain(excluded).next() {
+                return Err(Error::Input(format!(
+                    "No genome is left out of {}: {} failed, {} filtered out ({}: {})",
+                    config.inputs.len(),
+                    sketched.failures.len(),
+                    sketched.filters.len(),
+                    input,
+                    reason
+                ))
+                .into());
+            }
+        }
+        Ok(sketched)
+    }
+
+    /// Genome size outliers among `stats`, if detection is enabled
+    pub fn size_outliers(&self, stats: &[AssemblyStats]) -> anyhow::Result<Vec<SeqStats>> {
+        let Some(epsilon) = self.config.filter.outliers else {
+            return Ok(Vec::new());
+        };
+        let stats: Vec<SeqStats> = stats.iter().map(AssemblyStats::seq_stats).collect();
+        utils::detect_outliers(&stats, epsilon)
+    }
+
+    /// Fail on genome size outliers, which bias the k computed from the mean
+    /// genome size of Mash sketches
+    fn check_outliers(&self, outliers: &[SeqStats]) -> anyhow::Result<()> {
+        // Scaled sketches grow with the genome, size outliers do not bias them
+        if !outliers.is_empty() && self.config.sketch.scaled.is_none() {
+            return Err(Error::Input(format!(
+                "Outliers detected in genome sizes, they negatively influence k selection: {}",
+                outliers
+                    .iter()
+                    .map(|(id, size)| format!("{} ({})", id, utils::format_genome_size(*size)))
+                    .format(", ")
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Distance matrix and tree of the sketches of one k-mer size
+    fn build(
+        &self,
+        k: u8,
+        sketches: Vec<Sketch>,
+        timings: &mut Timings,
+    ) -> anyhow::Result<KmerRun> {
+        let mut matrix = timings.time(format!("distances (k={})", k), || {
+            dist::distance_to_matrix(dist::compute_distances(sketches.clone()))
+        });
+        dist::relabel(&mut matrix, &self.config.labels);
+        log::info!("Computed the distance matrix at k={}", k);
+        let mut negative_branches = NegativeBranchSummary::default();
+        let tree = match &self.config.tree {
+            Some(tree_config) => {
+                let canonical = tree_config.method == TreeMethod::Canonical;
+                let mut tree = timings.time(format!("tree (k={})", k), || {
+                    utils::compute_tree(&matrix, canonical, self.config.threads)
+                })?;
+                negative_branches = tree.fix_negative_branches(tree_config.negative_branches);
+                log::info!("Built the neighbor-joining tree at k={}", k);
+                Some(tree)
+            }
+            None => None,
+        };
+        Ok(KmerRun {
+            k,
+            sketches,
+            matrix,
+            tree,
+            negative_branches,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::inputs::tests::open_count;
+
+    #[test]
+    fn test_pipeline_builder() {
+        let pipeline = Pipeline::new(["a.fna", "b.fna"])
+            .kmer_sizes([16, 21])
+            .scaled(Some(1000))
+            .tree_method(TreeMethod::Canonical)
+            .threads(0);
+        let config = pipeline.config();
+        assert_eq!(config.inputs, vec!["a.fna", "b.fna"]);
+        assert_eq!(config.sketch.kmer_sizes, vec![16, 21]);
+        assert_eq!(config.tree.as_ref().unwrap().method, TreeMethod::Canonical);
+        assert_eq!(config.threads, 1);
+        assert!(pipeline.clone().no_tree().config().tree.is_none());
+
+        let json = config.to_json(&[16, 21]);
+        assert_eq!(json["kmer_sizes"][1], 21);
+        assert_eq!(json["kmer_sizes_computed"], false);
+        assert_eq!(json["tree_method"], "canonical");
+        assert_eq!(json["negative_branches"], "keep");
+        assert_eq!(json["on_error"], "abort");
+    }
+
+    #[test]
+    fn test_filter_exclusion() {
+        let stats = AssemblyStats {
+            length: 2_000_000,
+            n_count: 300_000,
+            ..Default::default()
+        };
+        let mut filter = FilterConfig::default();
+        assert_eq!(filter.exclusion(&stats), None);
+        filter.max_n_fraction = Some(0.1);
+        filter.max_genome_size = Some(1_500_000);
+        assert_eq!(
+            filter.exclusion(&stats).unwrap(),
+            "genome size 2.0 Mb is above the maximum of 1.5 Mb; \
+             N fraction 0.1500 is above the maximum of 0.1"
+        );
+    }
+
+    #[test]
+    fn test_pipeline_run() {
+        let result = Pipeline::new(["test/bacam.fna", "test/bacsp.fna"])
+            .kmer_sizes([21])
+            .no_tree()
+            .run()
+            .unwrap();
+        assert_eq!(result.stats.len(), 2);
+        assert_eq!(result.stats[0].records, 1);
+        let table = result.genome_stats();
+        assert_eq!(table[1].name, "bacsp");
+        assert_eq!(table[1].assembly, result.stats[1]);
+        assert!(table[1].distinct_kmers.unwrap() > 1_000_000);
+        assert_eq!(result.sketches().len(), 2);
+        assert_eq!(result.matrix().size(), 2);
+        assert!(result.tree().is_none());
+        let stages: Vec<&str> = result
+            .timings
+            .stages()
+            .iter()
+            .map(|(stage, _)| stage.as_str())
+            .collect();
+        assert_eq!(stages, vec!["sketching", "distances (k=21)"]);
+
+        // bacsp is 3.93 Mb long, k comes from the given genome size
+        let sketched = Pipeline::new(["test/bacam.fna", "test/bacsp.fna"])
+            .genome_size(Some(units::parse_size("100M").unwrap()))
+            .min_genome_size(Some(3_950_000))
+            .no_tree()
+            .stats_and_sketches()
+            .unwrap();
+        assert_eq!(sketched.per_k[0].0, 17);
+        assert_eq!(sketched.inputs, vec!["test/bacam.fna"]);
+        assert_eq!(sketched.filters[1].input, "test/bacsp.fna");
+        assert!(sketched.filters[1].excluded.is_some());
+        let err = Pipeline::new(["test/bacam.fna", "test/bacsp.fna"])
+            .kmer_sizes([21])
+            .min_genome_size(Some(4_000_000))
+            .no_tree()
+            .run()
+            .unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 3);
+
+        // Distances need at least two genomes, trees three
+        let err = Pipeline::new(["test/bacam.fna"])
+            .kmer_sizes([21])
+            .no_tree()
+            .run()
+            .unwrap_err();
+        assert!(err.to_string().contains("Computing distances"));
+        let err = Pipeline::new(["test/bacam.fna", "test/bacsp.fna"])
+            .run()
+            .unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 3);
+        assert!(err.to_string().contains("neighbor-joining tree"));
+    }
+
+    #[test]
+    fn test_single_pass() {
+        // Each genome is read once, also when k is computed from their sizes
+        let dir = tempfile::tempdir().unwrap();
+        let inputs: Vec<String> = ["bacam", "bacsp"]
+            .iter()
+            .map(|name| {
+                let path = dir.path().join(format!("{}.fna", name));
+                std::fs::copy(format!("test/{}.fna", name), &path).unwrap();
+                path.to_str().unwrap().to_string()
+            })
+            .collect();
+        let pipeline = Pipeline::new(inputs.clone()).no_tree();
+        assert!(pipeline.computes_kmer_size());
+        let sketched = pipeline.stats_and_sketches().unwrap();
+        for input in &inputs {
+            assert_eq!(open_count(input), 1);
+        }
+        let k = pipeline.kmer_sizes_for(&sketched.stats)[0];
+        assert_eq!(sketched.per_k.len(), 1);
+        assert_eq!(sketched.per_k[0].0, k);
+        assert!(pipeline.candidate_kmer_sizes().contains(&k));
+
+        // The same sketches as with k given
+        let fixed = Pipeline::new(inputs.clone())
+            .kmer_sizes([k])
+            .no_tree()
+            .stats_and_sketches()
+            .unwrap();
+        assert_eq!(fixed.per_k[0].1[1].hashes, sketched.per_k[0].1[1].hashes);
+        for input in &inputs {
+            assert_eq!(open_count(input), 2);
+        }
+    }
+
+    #[test]
+    fn test_cluster_size_outliers() {
+        // A quarter of bacam is a genome size outlier
+        let dir = tempfile::tempdir().unwrap();
+        let small = dir.path().join("small.fna");
+        let bacam = std::fs::read_to_string("test/bacam.fna").unwrap();
+        std::fs::write(&small, &bacam[..bacam.len() / 4]).unwrap();
+        let inputs = ["test/bacam.fna", "test/bacsp.fna", small.to_str().unwrap()];
+
+        let err = Pipeline::new(inputs).no_tree().run().unwrap_err();
+        assert!(err.to_string().contains("Outliers"));
+
+        // With a fixed k, outliers are only reported
+        let result = Pipeline::new(inputs)
+            .kmer_sizes([21])
+            .no_tree()
+            .run()
+            .unwrap();
+        assert_eq!(result.outliers.len(), 1);
+        assert_eq!(result.outliers[0].0, "FN597644.1");
+        let outliers: Vec<bool> = result.genome_stats().iter().map(|g| g.outlier).collect();
+        assert_eq!(outliers, [false, false, true]);
+        // Statistics alone do not fail on outliers
+        assert_eq!(Pipeline::new(inputs).genome_stats().unwrap().len(), 3);
+        let clustering = crate::cluster::cluster(
+            result.matrix(),
+            crate::cluster::Linkage::Average,
+            crate::cluster::ani_to_distance(95.0),
+        );
+        // bacam and its fragment cluster together
+        assert_eq!(clustering.n_clusters(), 2);
+    }
+
+    #[test]
+    fn test_pipeline_skip_failures() {
+        let inputs = [
+            "test/bacam.fna",
+            "test/missing.fna",
+            "test/test.fq",
+            "test/bacsp.fna",
+        ];
+        let err = Pipeline::new(inputs)
+            .kmer_sizes([21])
+            .no_tree()
+            .run()
+            .unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 4);
+
+        let result = Pipeline::new(inputs)
+            .kmer_sizes([21])
+            .no_tree()
+            .on_error(OnError::Skip)
+            .run()
+            .unwrap();
+        assert_eq!(result.names, vec!["bacam", "bacsp"]);
+        let failed: Vec<&str> = result.failures.iter().map(|f| f.input.as_str()).collect();
+        assert_eq!(failed, vec!["test/missing.fna", "test/test.fq"]);
+        assert!(result.failures[1].reason.contains("too few kmers"));
+
+        // Two genomes are left, too few for a tree
+        let err = Pipeline::new(inputs)
+            .kmer_sizes([21])
+            .on_error(OnError::Skip)
+            .run()
+            .unwrap_err();
+        assert_eq!(crate::error::exit_code(&err), 3);
+        assert!(format!("{:#}", err).contains("skipping failures"));
+    }
+}
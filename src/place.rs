@@ -0,0 +1,348 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::collections::HashMap;
+use std::io::Write;
+
+use crate::error::Error;
+use crate::tree::{Node, NodeId, Tree};
+
+/// Weighting of the least-squares placement criterion
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Weighting {
+    /// Ordinary least squares
+    Ols,
+    /// Fitch-Margoliash, weights are 1/d²
+    #[default]
+    Fm,
+}
+
+/// Best position of a query on a reference tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placement {
+    pub query: String,
+    /// Node below the branch where the query is attached
+    pub edge: NodeId,
+    /// Distance between `edge` and the attachment point
+    pub distal_length: f64,
+    /// Length of the branch leading to the query
+    pub pendant_length: f64,
+    /// Weighted sum of squared residuals
+    pub error: f64,
+}
+
+/// Weighted sums over a set of leaves, with distances `d` measured from a
+/// node and query distances `δ`: Σw, Σwδ, Σwδ², Σwd, Σwd², Σwδd
+#[derive(Debug, Clone, Copy, Default)]
+struct Sums {
+    w: f64,
+    x1: f64,
+    x2: f64,
+    d1: f64,
+    d2: f64,
+    c: f64,
+}
+
+impl Sums {
+    fn add(&mut self, other: &Sums) {
+        self.w += other.w;
+        self.x1 += other.x1;
+        self.x2 += other.x2;
+        self.d1 += other.d1;
+        self.d2 += other.d2;
+        self.c += other.c;
+    }
+
+    fn sub(&self, other: &Sums) -> Sums {
+        Sums {
+            w: self.w - other.w,
+            x1: self.x1 - other.x1,
+            x2: self.x2 - other.x2,
+            d1: self.d1 - other.d1,
+            d2: self.d2 - other.d2,
+            c: self.c - other.c,
+        }
+    }
+
+    /// Same sums with distances measured `e` further away
+    fn shift(&self, e: f64) -> Sums {
+        Sums {
+            d1: self.d1 + e * self.w,
+            d2: self.d2 + 2.0 * e * self.d1 + e * e * self.w,
+            c: self.c + e * self.x1,
+            ..*self
+        }
+    }
+
+    /// Σw(δ - d) and Σw(δ - d)²
+    fn residuals(&self) -> (f64, f64) {
+        (self.x1 - self.d1, self.x2 - 2.0 * self.c + self.d2)
+    }
+}
+
+/// Place a query on the branch minimising the weighted least-squares error
+/// between its distances to the leaves and the tree path lengths, as in
+/// APPLES (Balaban et al., 2020, 10.1093/sysbio/syz063).
+///
+/// `distances` holds the query distance to each leaf label; every labelled
+/// leaf of the tree must have one. Runs in linear time in the tree size.
+pub fn place_query(
+    tree: &Tree,
+    query: &str,
+    distances: &HashMap<String, f64>,
+    weighting: Weighting,
+) -> anyhow::Result<Placement> {
+    let length = |id: NodeId| tree.node(id).length.unwrap_or(0.0).max(0.0);
+
+    // Sums over the leaves below each node
+    let mut down = vec![Sums::default(); tree.len()];
+    for id in tree.postorder() {
+        let node = tree.node(id);
+        if node.is_leaf() {
+            let label = node.label.as_deref().unwrap_or("");
+            let delta = *distances.get(label).ok_or_else(|| {
+                Error::Distance(format!(
+                    "No distance between {} and reference {}",
+                    query, label
+                ))
+            })?;
+            let w = match weighting {
+                Weighting::Ols => 1.0,
+                // Floor the distance to avoid infinite weights for identical genomes
+                Weighting::Fm => 1.0 / delta.max(1e-4).powi(2),
+            };
+            down[id] = Sums {
+                w,
+                x1: w * delta,
+                x2: w * delta * delta,
+                ..Default::default()
+            };
+        } else {
+            let mut sums = Sums::default();
+            for &child in &node.children {
+                sums.add(&down[child].shift(length(child)));
+            }
+            down[id] = sums;
+        }
+    }
+
+    // Sums over the leaves outside of each subtree, measured from the node
+    let mut up = vec![Sums::default(); tree.len()];
+    for id in tree.preorder() {
+        let node = tree.node(id);
+        let mut all = up[id];
+        all.add(&down[id]);
+        for &child in &node.children {
+            up[child] = all
+                .sub(&down[child].shift(length(child)))
+                .shift(length(child));
+        }
+    }
+
+    let mut best: Option<Placement> = None;
+    for id in tree.preorder().into_iter().skip(1) {
+        let e = length(id);
+        let (inside, outside) = (down[id], up[id]);
+        let (sa, saa) = inside.residuals();
+        let (sb, sbb) = outside.residuals();
+        let (wa, wb) = (inside.w, outside.w);
+        let total = wa + wb;
+        if total <= 0.0 {
+            continue;
+        }
+
+        // Query attached at distance a above `id` with pendant length l:
+        // inside leaves are at l + a + d, outside leaves at l - a + d
+        let error = |l: f64, a: f64| {
+            saa - 2.0 * (l + a) * sa + wa * (l + a).powi(2) + sbb - 2.0 * (l - a) * sb
+                + wb * (l - a).powi(2)
+        };
+        let best_l = |a: f64| ((sa + sb - (wa - wb) * a) / total).max(0.0);
+        let best_a = |l: f64| ((sa - sb - (wa - wb) * l) / total).clamp(0.0, e);
+
+        let mut a = if wa > 0.0 && wb > 0.0 {
+            (sa / wa - sb / wb) / 2.0
+        } else {
+            0.0
+        }
+        .clamp(0.0, e);
+        let mut l = best_l(a);
+        // Refine once when the pendant length hit zero
+        if l == 0.0 {
+            a = best_a(l);
+            l = best_l(a);
+        }
+
+        let err = error(l, a);
+        if !best.as_ref().is_some_and(|b| err >= b.error) {
+            best = Some(Placement {
+                query: query.to_string(),
+                edge: id,
+                distal_length: a,
+                pendant_length: l,
+                error: err,
+            });
+        }
+    }
+
+    best.ok_or_else(|| {
+        Error::Tree(format!("Reference tree has no branch to place {}", query)).into()
+    })
+}
+
+/// Insert the placed queries as new leaves of `tree`.
+///
+/// Placements must have been computed on this tree; queries placed on the
+/// same branch are inserted from the bottom of the branch upwards.
+pub fn insert_placements(tree: &mut Tree, placements: &[Placement]) {
+    let mut by_edge: HashMap<NodeId, Vec<&Placement>> = HashMap::new();
+    for placement in placements {
+        by_edge.entry(placement.edge).or_default().push(placement);
+    }
+
+    for (edge, mut on_edge) in by_edge {
+        on_edge.sort_by(|a, b| a.distal_length.total_cmp(&b.distal_length));
+        let mut below = edge;
+        let mut offset = 0.0;
+        for placement in on_edge {
+            let node = tree.split_edge(below, placement.distal_length - offset);
+            tree.add_child(
+                node,
+                Node {
+                    label: Some(placement.query.clone()),
+                    length: Some(placement.pendant_length),
+                    ..Default::default()
+                },
+            );
+            below = node;
+            offset = placement.distal_length;
+        }
+    }
+}
+
+/// Name of a branch: the leaf label, or the first and last leaves of the clade
+pub fn edge_name(tree: &Tree, id: NodeId) -> String {
+    let mut first = id;
+    while let Some(&child) = tree.node(first).children.first() {
+        first = child;
+    }
+    let mut last = id;
+    while let Some(&child) = tree.node(last).children.last() {
+        last = child;
+    }
+    let label = |n: NodeId| tree.node(n).label.clone().unwrap_or_default();
+    if first == last {
+        label(first)
+    } else {
+        format!("{}|{}", label(first), label(last))
+    }
+}
+
+/// Write the placement report as TSV
+pub fn write_placements<W: Write>(
+    out: &mut W,
+    tree: &Tree,
+    placements: &[Placement],
+) -> anyhow::Result<()> {
+    writeln!(out, "query\tedge\tdistal_length\tpendant_length\terror")?;
+    for p in placements {
+        writeln!(
+            out,
+            "{}\t{}\t{}\t{}\t{}",
+            p.query,
+            edge_name(tree, p.edge),
+            p.distal_length,
+            p.pendant_length,
+            p.error
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::NewickOptions;
+
+    fn example() -> Tree {
+        Tree::from_newick("((A:0.1,B:0.2):0.05,C:0.3,D:0.4);").unwrap()
+    }
+
+    fn distances(values: [f64; 4]) -> HashMap<String, f64> {
+        ["A", "B", "C", "D"]
+            .iter()
+            .zip(values)
+            .map(|(l, d)| (l.to_string(), d))
+            .collect()
+    }
+
+    #[test]
+    fn test_place_query_exact() {
+        // Query attached 0.04 above A with a pendant branch of 0.02
+        let tree = example();
+        let delta = distances([0.06, 0.28, 0.43, 0.53]);
+        for weighting in [Weighting::Ols, Weighting::Fm] {
+            let placement = place_query(&tree, "Q", &delta, weighting).unwrap();
+            assert_eq!(edge_name(&tree, placement.edge), "A");
+            assert!((placement.distal_length - 0.04).abs() < 1e-9);
+            assert!((placement.pendant_length - 0.02).abs() < 1e-9);
+            assert!(placement.error < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_place_query_internal_edge() {
+        // Query attached in the middle of the (A,B) stem with no pendant branch
+        let tree = example();
+        let delta = distances([0.125, 0.225, 0.325, 0.425]);
+        let placement = place_query(&tree, "Q", &delta, Weighting::Ols).unwrap();
+        assert_eq!(edge_name(&tree, placement.edge), "A|B");
+        assert!((placement.distal_length - 0.025).abs() < 1e-9);
+        assert!(placement.pendant_length.abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_place_query_missing_reference() {
+        let tree = example();
+        let mut delta = distances([0.1, 0.2, 0.3, 0.4]);
+        delta.remove("C");
+        assert!(place_query(&tree, "Q", &delta, Weighting::Ols).is_err());
+    }
+
+    #[test]
+    fn test_insert_placements() {
+        let mut tree = example();
+        let a = tree.leaves()[0];
+        let placements = vec![
+            Placement {
+                query: "Q2".to_string(),
+                edge: a,
+                distal_length: 0.06,
+                pendant_length: 0.01,
+                error: 0.0,
+            },
+            Placement {
+                query: "Q1".to_string(),
+                edge: a,
+                distal_length: 0.02,
+                pendant_length: 0.03,
+                error: 0.0,
+            },
+        ];
+        insert_placements(&mut tree, &placements);
+        let options = NewickOptions {
+            precision: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            tree.to_newick(&options),
+            "((((A:0.02,Q1:0.03):0.04,Q2:0.01):0.04,B:0.20):0.05,C:0.30,D:0.40);"
+        );
+
+        let mut out = Vec::new();
+        write_placements(&mut out, &tree, &placements).unwrap();
+        assert!(String::from_utf8(out).unwrap().starts_with("query\tedge\t"));
+    }
+}
@@ -0,0 +1,85 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+//! Progress bars of long steps, drawn on stderr.
+//!
+//! Bars are off until [`set_enabled`] turns them on, and are hidden anyway
+//! when stderr is not a terminal. Log lines go through [`Logger`] so that
+//! they are written above the bars instead of breaking them.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static BARS: OnceLock<MultiProgress> = OnceLock::new();
+
+const BAR_TEMPLATE: &str =
+    "{msg:>12} [{elapsed_precise}] {wide_bar} {human_pos}/{human_len} (ETA {eta})";
+
+/// Draw progress bars from now on (if stderr is a terminal)
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Bar counting `len` items of the step `message`, with its ETA
+pub fn bar(len: u64, message: &'static str) -> ProgressBar {
+    if !is_enabled() {
+        return ProgressBar::hidden();
+    }
+    let style = ProgressStyle::with_template(BAR_TEMPLATE).expect("valid progress template");
+    bars().add(
+        ProgressBar::new(len)
+            .with_style(style)
+            .with_message(message),
+    )
+}
+
+fn bars() -> &'static MultiProgress {
+    BARS.get_or_init(MultiProgress::new)
+}
+
+/// Hide the bars while running `f`, to write on stderr
+pub fn suspend<R>(f: impl FnOnce() -> R) -> R {
+    bars().suspend(f)
+}
+
+/// Logger writing its records above the progress bars
+pub struct Logger<L>(pub L);
+
+impl<L: log::Log> log::Log for Logger<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.0.enabled(record.metadata()) {
+            suspend(|| self.0.log(record));
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disabled_bars_are_hidden() {
+        assert!(!is_enabled());
+        assert!(bar(10, "Sketching").is_hidden());
+        assert!(bar(10, "Tree").is_hidden());
+        assert_eq!(suspend(|| 1), 1);
+    }
+}
@@ -0,0 +1,336 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use finch::{
+    serialization::Sketch,
+    sketch_schemes::{mash::MashSketcher, scaled::ScaledSketcher, SketchParams, SketchScheme},
+};
+
+use crate::error::Error;
+use crate::filter::{self, RecordCounts, RecordFilter};
+use crate::inputs;
+use crate::sketch;
+use crate::utils::{AssemblyStats, StatsCollector};
+
+/// Default amino-acid k-mer size, as in Mash
+pub const DEFAULT_KMER: u8 = 9;
+
+/// Standard genetic code, codons ordered TCAG
+const CODE: &[u8; 64] = b"FFLLSSSSYY**CC*WLLLLPPPPHHQQRRRRIIIMTTTTNNKKSSRRVVVVAAAADDEEGGGG";
+
+fn base_index(base: u8) -> Option<usize> {
+    match base.to_ascii_uppercase() {
+        b'T' | b'U' => Some(0),
+        b'C' => Some(1),
+        b'A' => Some(2),
+        b'G' => Some(3),
+        _ => None,
+    }
+}
+
+/// Translate a nucleotide sequence in its first frame; codons with
+/// ambiguous bases become `X` and stop codons `*`
+pub fn translate(seq: &[u8]) -> Vec<u8> {
+    seq.chunks_exact(3)
+        .map(|codon| {
+            match (
+                base_index(codon[0]),
+                base_index(codon[1]),
+                base_index(codon[2]),
+            ) {
+                (Some(a), Some(b), Some(c)) => CODE[16 * a + 4 * b + c],
+                _ => b'X',
+            }
+        })
+        .collect()
+}
+
+fn reverse_complement(seq: &[u8]) -> Vec<u8> {
+    seq.iter()
+        .rev()
+        .map(|&b| match b.to_ascii_uppercase() {
+            b'A' => b'T',
+            b'C' => b'G',
+            b'G' => b'C',
+            b'T' | b'U' => b'A',
+            _ => b'N',
+        })
+        .collect()
+}
+
+/// Translations of the three forward and three reverse frames
+pub fn six_frames(seq: &[u8]) -> Vec<Vec<u8>> {
+    let rc = reverse_complement(seq);
+    (0..3)
+        .map(|f| translate(seq.get(f..).unwrap_or_default()))
+        .chain((0..3).map(|f| translate(rc.get(f..).unwrap_or_default())))
+        .collect()
+}
+
+/// Comment of amino-acid sketches: finch writes every .msh file with the
+/// nucleotide alphabet, so the comment is what tells them apart
+pub const SKETCH_COMMENT: &str = "alphabet=amino-acids";
+
+/// Whether a sketch holds amino-acid k-mers, see [`SKETCH_COMMENT`]
+pub fn is_protein_sketch(sketch: &Sketch) -> bool {
+    sketch.comment == SKETCH_COMMENT
+}
+
+/// Check that sketches are all of amino-acid k-mers or all of nucleotide
+/// k-mers, as the two cannot be compared
+pub fn check_alphabets<'a>(sketches: impl IntoIterator<Item = &'a Sketch>) -> anyhow::Result<()> {
+    let mut sketches = sketches.into_iter();
+    let Some(first) = sketches.next() else {
+        return Ok(());
+    };
+    let protein = is_protein_sketch(first);
+    if let Some(other) = sketches.find(|s| is_protein_sketch(s) != protein) {
+        let (aa, nt) = if protein {
+            (first, other)
+        } else {
+            (other, first)
+        };
+        return Err(Error::Sketch(format!(
+            "Cannot compare amino-acid and nucleotide sketches: {} is of amino acids, {} of nucleotides",
+            aa.name, nt.name
+        ))
+        .into());
+    }
+    Ok(())
+}
+
+/// Whether a sequence looks like a protein: more than 10% of its letters
+/// are not nucleotides
+pub fn is_protein(seq: &[u8]) -> bool {
+    let letters = seq.iter().filter(|b| b.is_ascii_alphabetic()).count();
+    let others = seq
+        .iter()
+        .filter(|b| b.is_ascii_alphabetic() && !b"ACGTUNacgtun".contains(b))
+        .count();
+    letters > 0 && others * 10 > letters
+}
+
+/// Finch sketcher fed with amino-acid k-mers
+enum Sketcher {
+    Mash(MashSketcher),
+    Scaled(ScaledSketcher),
+}
+
+impl Sketcher {
+    fn new(params: &SketchParams) -> anyhow::Result<Self> {
+        match *params {
+            SketchParams::Mash {
+                kmers_to_sketch,
+                kmer_length,
+                hash_seed,
+                ..
+            } => Ok(Sketcher::Mash(MashSketcher::new(
+                kmers_to_sketch,
+                kmer_length,
+                hash_seed,
+            ))),
+            SketchParams::Scaled {
+                kmers_to_sketch,
+                kmer_length,
+                scale,
+                hash_seed,
+            } => Ok(Sketcher::Scaled(ScaledSketcher::new(
+                kmers_to_sketch,
+                scale,
+                kmer_length,
+                hash_seed,
+            ))),
+            SketchParams::AllCounts { .. } => Err(Error::Sketch(
+                "Unsupported sketch scheme for amino acids: all counts".to_string(),
+            )
+            .into()),
+        }
+    }
+
+    /// Push every k-mer of `protein` that has no stop codon or unknown residue
+    fn push_protein(&mut self, protein: &[u8], k: usize) {
+        for segment in protein.split(|&aa| aa == b'*' || aa == b'X') {
+            for kmer in segment.windows(k) {
+                match self {
+                    Sketcher::Mash(s) => s.push(kmer, 0),
+                    Sketcher::Scaled(s) => s.push(kmer, 0),
+                }
+            }
+        }
+    }
+
+    fn scheme(&self) -> &dyn SketchScheme {
+        match self {
+            Sketcher::Mash(s) => s,
+            Sketcher::Scaled(s) => s,
+        }
+    }
+}
+
+/// Read a sequence file once, filtering its records, collecting their
+/// statistics and sketching their amino-acid k-mers with each of `params`.
+///
+/// Protein records are used as is, nucleotide records are translated in
+/// six frames; sketch sequence lengths are counted in residues.
+pub fn scan_file(
+    filename: &str,
+    params: &[SketchParams],
+    filter: &RecordFilter,
+) -> anyhow::Result<sketch::Scan> {
+    let Some((format, mut reader)) = sketch::detect_fastx(inputs::open(filename)?, filename)?
+    else {
+        return Ok(sketch::Scan {
+            format: None,
+            stats: AssemblyStats::default(),
+            sketches: Vec::new(),
+            filtered: RecordCounts::default(),
+        });
+    };
+    let mut sketchers: Vec<Sketcher> = params
+        .iter()
+        .map(Sketcher::new)
+        .collect::<anyhow::Result<_>>()?;
+    let mut stats = StatsCollector::default();
+    let mut filtered = RecordCounts::default();
+    let mut seq_length = 0;
+
+    while let Some(record) = reader.next() {
+        let record = record.map_err(|e| {
+            Error::Sketch(format!("Invalid sequence record in {}: {}", filename, e))
+        })?;
+        let length = record.num_bases();
+        if !filter.keeps(length) {
+            filtered.dropped_records += 1;
+            filtered.dropped_bases += length;
+            continue;
+        }
+        let mut seq = record.seq().into_owned();
+        let proteins = if is_protein(&seq) {
+            stats.add_protein_record(record.id(), &seq);
+            vec![seq.to_ascii_uppercase()]
+        } else {
+            stats.add_record(record.id(), record.raw_seq());
+            if filter.mask_low_complexity {
+                filtered.masked_bases += filter::mask_low_complexity(&mut seq);
+            }
+            six_frames(&seq)
+        };
+        for protein in &proteins {
+            seq_length += protein.len() as u64;
+            for (sketcher, p) in sketchers.iter_mut().zip(params) {
+                sketcher.push_protein(protein, usize::from(p.k()));
+            }
+        }
+    }
+
+    let sketches = params
+        .iter()
+        .zip(sketchers)
+        .map(|(sketch_params, sketcher)| {
+            let mut filter_params = sketch::filter_params();
+            let (_, num_valid_kmers) = sketcher.scheme().total_bases_and_kmers();
+            let mut hashes = filter_params.filter_counts(&sketcher.scheme().to_vec());
+            sketch_params.process_post_filter(&mut hashes, filename)?;
+            Ok(Sketch {
+                name: filename.to_string(),
+                seq_length,
+                num_valid_kmers,
+                comment: SKETCH_COMMENT.to_string(),
+                hashes,
+                filter_params,
+                sketch_params: sketch_params.clone(),
+            })
+        })
+        .collect::<anyhow::Result<_>>()?;
+    Ok(sketch::Scan {
+        format: Some(format),
+        stats: stats.finish(),
+        sketches,
+        filtered,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_translate() {
+        assert_eq!(translate(b"ATGGCCTAAtgN"), b"MA*X");
+        let frames = six_frames(b"ATGAAATTT");
+        assert_eq!(frames.len(), 6);
+        assert_eq!(frames[0], b"MKF");
+        // Reverse complement is AAATTTCAT
+        assert_eq!(frames[3], b"KFH");
+        assert_eq!(frames[5], b"IS");
+    }
+
+    #[test]
+    fn test_is_protein() {
+        assert!(is_protein(b"MKVLAAGIVGLLLAQ"));
+        assert!(!is_protein(b"ACGTNNACGTACGT"));
+        assert!(!is_protein(b""));
+    }
+
+    #[test]
+    fn test_scan_file() {
+        let params = [
+            sketch::mash_params(DEFAULT_KMER, 1000, 200, 42),
+            sketch::mash_params(6, 1000, 200, 42),
+        ];
+        let sketches = scan_file("test/bacam.fna", &params, &RecordFilter::default())
+            .and_then(|scan| scan.checked("test/bacam.fna"))
+            .unwrap()
+            .sketches;
+        assert_eq!(sketches.len(), 2);
+        assert_eq!(sketches[0].hashes.len(), 1000);
+        assert_eq!(sketches[1].sketch_params.k(), 6);
+
+        // A protein file is sketched without translation
+        let mut file = tempfile::Builder::new().suffix(".faa").tempfile().unwrap();
+        writeln!(file, ">p1\nMKVLAAGIVGLLLAQ*MKVLAAGIVG").unwrap();
+        file.flush().unwrap();
+        let path = file.path().to_str().unwrap();
+        let protein = scan_file(
+            path,
+            &[sketch::mash_params(5, 10, 1, 42)],
+            &RecordFilter::default(),
+        )
+        .and_then(|scan| scan.checked(path))
+        .unwrap()
+        .sketches;
+        assert_eq!(protein[0].seq_length, 26);
+        // 11 + 6 k-mers, the last 6 repeat the first ones
+        assert_eq!(protein[0].num_valid_kmers, 17);
+        assert_eq!(protein[0].hashes.len(), 10);
+
+        let itself = finch::distance::distance(&sketches[0], &sketches[0], false).unwrap();
+        assert_eq!(itself.mash_distance, 0.0);
+    }
+
+    #[test]
+    fn test_protein_genome() {
+        // A multi-record protein file is one genome, without base composition
+        let mut file = tempfile::Builder::new().suffix(".faa").tempfile().unwrap();
+        writeln!(file, ">p1\nMNGSCQNNGSC\n>p2\nMKVLAAGIVGLLLAQ").unwrap();
+        file.flush().unwrap();
+        let path = file.path().to_str().unwrap();
+        let params = [sketch::mash_params(5, 10, 1, 42)];
+        let scan = scan_file(path, &params, &RecordFilter::default()).unwrap();
+        assert_eq!(scan.stats.records, 2);
+        assert_eq!(scan.stats.length, 26);
+        assert!(scan.stats.amino_acids);
+        assert_eq!((scan.stats.gc_count, scan.stats.n_count), (0, 0));
+        assert_eq!(crate::utils::sequence_problem(&scan), None);
+
+        // Amino-acid and nucleotide sketches are not compared
+        assert!(is_protein_sketch(&scan.sketches[0]));
+        let nucleotides = sketch::sketch_file_multi("test/bacam.fna", &params).unwrap();
+        assert!(check_alphabets(&scan.sketches).is_ok());
+        let err = check_alphabets(scan.sketches.iter().chain(&nucleotides)).unwrap_err();
+        assert!(err.to_string().contains("amino-acid and nucleotide"));
+    }
+}
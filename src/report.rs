@@ -0,0 +1,161 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use crate::error::Error;
+use crate::stats::{self, GenomeStats};
+use crate::utils::SeqStats;
+
+/// Version of finch the sketches are computed with
+pub const FINCH_VERSION: &str = env!("CEDAR_FINCH_VERSION");
+
+/// Wall-clock time of each stage of a run, in order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Timings(Vec<(String, Duration)>);
+
+impl Timings {
+    /// Run `f` as stage `stage` and record how long it took
+    pub fn time<T>(&mut self, stage: impl Into<String>, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let value = f();
+        self.0.push((stage.into(), start.elapsed()));
+        value
+    }
+
+    pub fn add(&mut self, stage: impl Into<String>, time: Duration) {
+        self.0.push((stage.into(), time));
+    }
+
+    pub fn stages(&self) -> &[(String, Duration)] {
+        &self.0
+    }
+}
+
+/// SHA-256 of the content of a file, in hexadecimal
+pub fn sha256_file(path: &str) -> anyhow::Result<String> {
+    let mut file = File::open(path).map_err(|e| Error::io(path, e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer).map_err(|e| Error::io(path, e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// SHA-256 of `data`, in hexadecimal
+pub fn sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Provenance of a run: what was run on which genomes, with which
+/// parameters, how long each stage took and what it wrote
+#[derive(Debug, Clone, Default)]
+pub struct RunReport {
+    /// Command line, program name included
+    pub command: Vec<String>,
+    /// Parameters of the run, k-mer sizes as used (computed or not)
+    pub parameters: Value,
+    pub genomes: Vec<GenomeStats>,
+    /// Genome size outliers, see [`crate::utils::detect_outliers`]
+    pub outliers: Vec<SeqStats>,
+    /// Genomes left out of the run, with the reason
+    pub excluded: Vec<(String, String)>,
+    pub timings: Timings,
+    /// Output files (or "-" for stdout) with the SHA-256 of their content
+    pub outputs: Vec<(String, String)>,
+}
+
+impl RunReport {
+    pub fn to_json(&self) -> Value {
+        json!({
+            "cedar_version": env!("CARGO_PKG_VERSION"),
+            "finch_version": FINCH_VERSION,
+            "command": self.command,
+            "parameters": self.parameters,
+            "genomes": self.genomes.iter().map(stats::to_json).collect::<Vec<_>>(),
+            "outliers": self
+                .outliers
+                .iter()
+                .map(|(id, length)| json!({ "genome": id, "length": length }))
+                .collect::<Vec<_>>(),
+            "excluded": self
+                .excluded
+                .iter()
+                .map(|(input, reason)| json!({ "input": input, "reason": reason }))
+                .collect::<Vec<_>>(),
+            "timings": self
+                .timings
+                .stages()
+                .iter()
+                .map(|(stage, time)| json!({ "stage": stage, "seconds": time.as_secs_f64() }))
+                .collect::<Vec<_>>(),
+            "outputs": self
+                .outputs
+                .iter()
+                .map(|(path, sha256)| json!({ "path": path, "sha256": sha256 }))
+                .collect::<Vec<_>>(),
+        })
+    }
+
+    /// Write the report as pretty-printed JSON
+    pub fn write(&self, out: &mut dyn Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut *out, &self.to_json())?;
+        writeln!(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha256() {
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("abc.txt");
+        std::fs::write(&path, b"abc").unwrap();
+        assert_eq!(sha256_file(path.to_str().unwrap()).unwrap(), sha256(b"abc"));
+    }
+
+    #[test]
+    fn test_run_report() {
+        let mut timings = Timings::default();
+        let value = timings.time("sketching", || 21);
+        assert_eq!(value, 21);
+        let report = RunReport {
+            command: vec!["darwin".to_string(), "a.fna".to_string()],
+            parameters: json!({ "kmer_sizes": [21] }),
+            outliers: vec![("big".to_string(), 9_000_000)],
+            timings,
+            outputs: vec![("tree.nwk".to_string(), sha256(b"(a,b,c);"))],
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        report.write(&mut out).unwrap();
+        let json: Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json["finch_version"], FINCH_VERSION);
+        assert_eq!(json["parameters"]["kmer_sizes"][0], 21);
+        assert_eq!(json["outliers"][0]["length"], 9_000_000);
+        assert_eq!(json["timings"][0]["stage"], "sketching");
+        assert_eq!(json["outputs"][0]["path"], "tree.nwk");
+    }
+}
@@ -4,12 +4,23 @@
 // to those terms.
 
 use std::fs::File;
+use std::io::{BufRead, Write};
 use std::path::{Path, PathBuf};
 
 use finch::{
-    errors::FinchResult, filtering::FilterParams, serialization::write_mash_file, sketch_files,
-    sketch_schemes::SketchParams,
+    errors::FinchResult,
+    filtering::FilterParams,
+    serialization::{write_finch_file, write_mash_file, MultiSketch, Sketch},
+    sketch_schemes::{SketchParams, SketchScheme},
 };
+use needletail::parser::Format;
+use rayon::prelude::*;
+
+use crate::error::Error;
+use crate::filter::{self, RecordCounts, RecordFilter};
+use crate::inputs;
+use crate::protein;
+use crate::utils::{AssemblyStats, StatsCollector};
 
 /// Compute the value of k that minimizes the probability of
 /// observing a random k-mer.
@@ -30,44 +41,387 @@ pub fn create_sketches(
     oversketch: usize,
     seed: u64,
     outdir: &str,
-) -> FinchResult<Vec<String>> {
-    // Create SketchParams struct for finch
-    let sketch_params = SketchParams::Mash {
+) -> anyhow::Result<Vec<String>> {
+    let sketch_params = mash_params(kmer_size, sketch_size, oversketch, seed);
+    write_sketches(filenames, &sketch_params, outdir)
+}
+
+/// Parameters of a bottom-k Mash sketch
+pub fn mash_params(
+    kmer_size: u8,
+    sketch_size: usize,
+    oversketch: usize,
+    seed: u64,
+) -> SketchParams {
+    SketchParams::Mash {
         kmers_to_sketch: sketch_size * oversketch,
         final_size: sketch_size,
         no_strict: false,
         kmer_length: kmer_size,
         hash_seed: seed,
-    };
+    }
+}
+
+/// Parameters of a FracMinHash sketch keeping the hashes below 1/`scaled`
+/// of the hash space, whatever the genome size
+pub fn scaled_params(kmer_size: u8, scaled: u64, seed: u64) -> SketchParams {
+    SketchParams::Scaled {
+        kmers_to_sketch: 0,
+        kmer_length: kmer_size,
+        scale: 1.0 / scaled as f64,
+        hash_seed: seed,
+    }
+}
 
-    // Create FilterParams struct for finch
-    let filter_params = FilterParams {
+/// FilterParams used for every sketch: no k-mer abundance filtering
+pub fn filter_params() -> FilterParams {
+    FilterParams {
         filter_on: Some(false),
         abun_filter: (Some(0u32), None),
         err_filter: 1.0,
         strand_filter: 0.1,
-    };
+    }
+}
 
-    // Process files and generate sketches
+/// Sketch fasta files and write one sketch file per input in `outdir`.
+///
+/// Mash sketches are written as `.msh`; the Mash format cannot store the
+/// scale of other schemes, which are written in the finch format (`.bsk`).
+pub fn write_sketches(
+    filenames: &[String],
+    sketch_params: &SketchParams,
+    outdir: &str,
+) -> anyhow::Result<Vec<String>> {
     filenames
         .iter()
         .map(|filename| {
-            let sketches = sketch_files(&[filename], &sketch_params, &filter_params)?;
-            let out_path = PathBuf::from(outdir).join(format!(
-                "{}.msh",
-                Path::new(filename).file_name().unwrap().to_string_lossy()
-            ));
-            let mut out_file = File::create(&out_path)?;
-            write_mash_file(&mut out_file, &sketches)?;
-            Ok(out_path.to_string_lossy().into_owned())
+            let sketches = sketch_file_multi(filename, std::slice::from_ref(sketch_params))?;
+            Ok(save_sketches(filename, &sketches, sketch_params, outdir)?)
         })
         .collect()
 }
 
+/// Write the sketches of `filename` in `outdir` and return the file path
+pub fn save_sketches(
+    filename: &str,
+    sketches: &[Sketch],
+    sketch_params: &SketchParams,
+    outdir: &str,
+) -> FinchResult<String> {
+    let extension = match sketch_params {
+        SketchParams::Mash { .. } => "msh",
+        _ => "bsk",
+    };
+    let name = if filename == inputs::STDIN {
+        "stdin".into()
+    } else {
+        Path::new(filename)
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+    };
+    let out_path = PathBuf::from(outdir).join(format!("{}.{}", name, extension));
+    let mut out_file = File::create(&out_path)?;
+    match sketch_params {
+        SketchParams::Mash { .. } => write_mash_file(&mut out_file, sketches)?,
+        _ => write_finch_file(&mut out_file, sketches)?,
+    }
+    Ok(out_path.to_string_lossy().into_owned())
+}
+
+/// Format of a stream of sketches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SketchFormat {
+    /// Binary Mash sketch file (.msh)
+    Mash,
+    /// Mash-compatible JSON (as `mash info -d`)
+    Json,
+}
+
+/// Write sketches sharing the same parameters to `out` in one stream
+pub fn write_sketch_stream(
+    out: &mut dyn Write,
+    sketches: &[Sketch],
+    format: SketchFormat,
+) -> anyhow::Result<()> {
+    match format {
+        SketchFormat::Mash => {
+            if sketches
+                .iter()
+                .any(|s| !matches!(s.sketch_params, SketchParams::Mash { .. }))
+            {
+                return Err(Error::Sketch(
+                    "Only bottom-k sketches can be written in the Mash format, use JSON"
+                        .to_string(),
+                )
+                .into());
+            }
+            write_mash_file(out, sketches)?;
+        }
+        SketchFormat::Json => {
+            let multi = MultiSketch::from_sketches(sketches)?;
+            serde_json::to_writer(&mut *out, &multi)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// What a single pass over a sequence file gives
+#[derive(Debug, Clone)]
+pub struct Scan {
+    /// Sequence format, `None` if the file is neither FASTA nor FASTQ
+    pub format: Option<Format>,
+    pub stats: AssemblyStats,
+    /// One sketch per sketch parameters
+    pub sketches: Vec<Sketch>,
+    /// What the record filters removed
+    pub filtered: RecordCounts,
+}
+
+impl Scan {
+    /// This scan, or an input error if `name` was not a sequence file
+    pub fn checked(self, name: &str) -> anyhow::Result<Self> {
+        if self.format.is_none() {
+            return Err(Error::Input(format!("Not a FASTA or FASTQ file: {}", name)).into());
+        }
+        Ok(self)
+    }
+}
+
+/// Read a sequence file (or [`inputs::STDIN`]) once: detect its format,
+/// filter its records, then collect their statistics and sketch them with
+/// each of `params` (e.g. several k-mer sizes)
+pub fn scan_file(
+    filename: &str,
+    params: &[SketchParams],
+    filter: &RecordFilter,
+) -> anyhow::Result<Scan> {
+    scan_reader(inputs::open(filename)?, filename, params, filter)
+}
+
+/// [`scan_file`] on an opened stream, whose format is peeked before parsing
+pub fn scan_reader(
+    reader: Box<dyn BufRead + Send + '_>,
+    name: &str,
+    params: &[SketchParams],
+    filter: &RecordFilter,
+) -> anyhow::Result<Scan> {
+    let Some((format, mut parser)) = detect_fastx(reader, name)? else {
+        return Ok(Scan {
+            format: None,
+            stats: AssemblyStats::default(),
+            sketches: Vec::new(),
+            filtered: RecordCounts::default(),
+        });
+    };
+    let mut stats = StatsCollector::default();
+    let mut filtered = RecordCounts::default();
+    let mut sketchers: Vec<_> = params.iter().map(|p| p.create_sketcher()).collect();
+    while let Some(record) = parser.next() {
+        let record = record
+            .map_err(|e| Error::Sketch(format!("Invalid sequence record in {}: {}", name, e)))?;
+        let length = record.num_bases();
+        if !filter.keeps(length) {
+            filtered.dropped_records += 1;
+            filtered.dropped_bases += length;
+            continue;
+        }
+        stats.add_record(record.id(), record.raw_seq());
+        if filter.mask_low_complexity {
+            let mut seq = record.seq().into_owned();
+            filtered.masked_bases += filter::mask_low_complexity(&mut seq);
+            for sketcher in &mut sketchers {
+                sketcher.process(&seq.as_slice());
+            }
+        } else {
+            for sketcher in &mut sketchers {
+                sketcher.process(&record);
+            }
+        }
+    }
+
+    let sketches = params
+        .iter()
+        .zip(sketchers)
+        .map(|(sketch_params, sketcher)| to_sketch(&*sketcher, sketch_params, name, ""))
+        .collect::<anyhow::Result<_>>()?;
+    Ok(Scan {
+        format: Some(format),
+        stats: stats.finish(),
+        sketches,
+        filtered,
+    })
+}
+
+/// Sketch a sequence file (or [`inputs::STDIN`]) with several sketch
+/// parameters (e.g. several k-mer sizes) while reading it only once
+pub fn sketch_file_multi(filename: &str, params: &[SketchParams]) -> anyhow::Result<Vec<Sketch>> {
+    sketch_reader(inputs::open(filename)?, filename, params)
+}
+
+/// Format of a sequence stream, peeked without consuming it, and its
+/// parser; `None` if the stream is empty or neither FASTA nor FASTQ
+pub fn detect_fastx<'a>(
+    mut reader: Box<dyn BufRead + Send + 'a>,
+    name: &str,
+) -> anyhow::Result<Option<(Format, Box<dyn needletail::FastxReader + 'a>)>> {
+    let format = match inputs::peek_format(&mut reader).map_err(|e| Error::io(name, e))? {
+        Some(b'>') => Format::Fasta,
+        Some(b'@') => Format::Fastq,
+        _ => return Ok(None),
+    };
+    let parser = needletail::parse_fastx_reader(reader)
+        .map_err(|e| Error::Sketch(format!("Could not read sequence file {}: {}", name, e)))?;
+    Ok(Some((format, parser)))
+}
+
+/// Parse a FASTA or FASTQ stream, failing on anything else
+pub fn fastx_reader<'a>(
+    reader: Box<dyn BufRead + Send + 'a>,
+    name: &str,
+) -> anyhow::Result<Box<dyn needletail::FastxReader + 'a>> {
+    detect_fastx(reader, name)?
+        .map(|(_, parser)| parser)
+        .ok_or_else(|| Error::Input(format!("Not a FASTA or FASTQ file: {}", name)).into())
+}
+
+/// Sketch every record of a sequence stream as one genome named `name`
+pub fn sketch_reader(
+    reader: Box<dyn BufRead + Send + '_>,
+    name: &str,
+    params: &[SketchParams],
+) -> anyhow::Result<Vec<Sketch>> {
+    let scan = scan_reader(reader, name, params, &RecordFilter::default())?;
+    Ok(scan.checked(name)?.sketches)
+}
+
+/// Sketch each record of a sequence file as its own genome, named by the
+/// record id and commented with the rest of its header
+pub fn sketch_records(filename: &str, params: &SketchParams) -> anyhow::Result<Vec<Sketch>> {
+    let mut reader = fastx_reader(inputs::open(filename)?, filename)?;
+    let mut sketches = Vec::new();
+    while let Some(record) = reader.next() {
+        let record = record.map_err(|e| {
+            Error::Sketch(format!("Invalid sequence record in {}: {}", filename, e))
+        })?;
+        let header = String::from_utf8_lossy(record.id()).into_owned();
+        let (id, comment) = header
+            .split_once(char::is_whitespace)
+            .unwrap_or((&header, ""));
+        let mut sketcher = params.create_sketcher();
+        sketcher.process(&record);
+        sketches.push(to_sketch(&*sketcher, params, id, comment.trim())?);
+    }
+    Ok(sketches)
+}
+
+fn to_sketch(
+    sketcher: &dyn SketchScheme,
+    sketch_params: &SketchParams,
+    name: &str,
+    comment: &str,
+) -> anyhow::Result<Sketch> {
+    let mut filter_params = filter_params();
+    let (seq_length, num_valid_kmers) = sketcher.total_bases_and_kmers();
+    let mut hashes = filter_params.filter_counts(&sketcher.to_vec());
+    sketch_params.process_post_filter(&mut hashes, name)?;
+    Ok(Sketch {
+        name: name.to_string(),
+        seq_length,
+        num_valid_kmers,
+        comment: comment.to_string(),
+        hashes,
+        filter_params,
+        sketch_params: sketch_params.clone(),
+    })
+}
+
+/// Estimated number of distinct k-mers of the sketched sequences, from the
+/// largest hash kept by a full Mash sketch or from the FracMinHash scale
+pub fn distinct_kmers(sketch: &Sketch) -> u64 {
+    let kept = sketch.hashes.len() as u64;
+    match sketch.sketch_params {
+        SketchParams::Scaled { scale, .. } => (kept as f64 / scale).round() as u64,
+        SketchParams::Mash { final_size, .. } if kept > 1 && kept >= final_size as u64 => {
+            let max_hash = sketch
+                .hashes
+                .iter()
+                .map(|h| h.hash)
+                .max()
+                .unwrap_or(u64::MAX);
+            ((kept - 1) as f64 * (u64::MAX as f64 / max_hash as f64)).round() as u64
+        }
+        _ => kept,
+    }
+}
+
+/// Parameters to sketch new sequences so that they can be compared to
+/// `sketches`
+pub fn params_like(sketches: &[Sketch], oversketch: usize) -> anyhow::Result<SketchParams> {
+    if sketches.is_empty() {
+        return Err(Error::Sketch("No sketches to read parameters from".to_string()).into());
+    }
+    match SketchParams::from_sketches(sketches)? {
+        SketchParams::Mash {
+            kmer_length,
+            final_size,
+            hash_seed,
+            no_strict,
+            ..
+        } => {
+            // Mash files do not record the sketch size
+            let size = if final_size > 0 {
+                final_size
+            } else {
+                sketches.iter().map(|s| s.hashes.len()).max().unwrap_or(0)
+            };
+            Ok(SketchParams::Mash {
+                kmers_to_sketch: size * oversketch,
+                final_size: size,
+                no_strict,
+                kmer_length,
+                hash_seed,
+            })
+        }
+        params @ SketchParams::Scaled { .. } => Ok(params),
+        SketchParams::AllCounts { .. } => {
+            Err(Error::Sketch("Unsupported sketch scheme: all counts".to_string()).into())
+        }
+    }
+}
+
+/// Whether `path` has the extension of a sketch file readable by finch
+pub fn is_sketch_file(path: &str) -> bool {
+    [".msh", ".bsk", ".sk"]
+        .iter()
+        .any(|ext| path.ends_with(ext))
+}
+
+/// Read sketch files in parallel, all of amino acids or all of nucleotides
+pub fn read_sketches<P: AsRef<Path> + Sync>(paths: &[P]) -> anyhow::Result<Vec<Sketch>> {
+    let sketches: Vec<Vec<Sketch>> = paths
+        .par_iter()
+        .map(|path| {
+            finch::open_sketch_file(path).map_err(|e| {
+                Error::Sketch(format!(
+                    "Could not read sketch file {}: {}",
+                    path.as_ref().display(),
+                    e
+                ))
+            })
+        })
+        .collect::<Result<_, Error>>()?;
+    let sketches: Vec<Sketch> = sketches.into_iter().flatten().collect();
+    protein::check_alphabets(&sketches)?;
+    Ok(sketches)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
+    use std::io::Write;
 
     #[test]
     fn test_create_sketches() {
@@ -99,4 +453,152 @@ mod tests {
 
         fs::remove_dir_all(outdir).unwrap();
     }
+
+    #[test]
+    fn test_read_sketches() {
+        let paths = ["test/sketches/bacam.fna.msh", "test/sketches/bacsp.fna.msh"];
+        let sketches = read_sketches(&paths).unwrap();
+        assert_eq!(sketches.len(), 2);
+        assert!(is_sketch_file(paths[0]));
+        assert!(!is_sketch_file("test/bacam.fna"));
+        let err = read_sketches(&["test/missing.msh"]).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::Sketch(_))));
+        match params_like(&sketches, 200).unwrap() {
+            SketchParams::Mash {
+                kmers_to_sketch,
+                final_size,
+                kmer_length,
+                hash_seed,
+                ..
+            } => assert_eq!(
+                (kmers_to_sketch, final_size, kmer_length, hash_seed),
+                (200_000, 1000, 21, 42)
+            ),
+            params => panic!("unexpected sketch parameters: {:?}", params),
+        }
+    }
+
+    #[test]
+    fn test_scaled_sketches() {
+        let filenames = ["test/bacam.fna".to_string(), "test/bacsp.fna".to_string()];
+        let outdir = tempfile::tempdir().unwrap();
+        let outdir = outdir.path().to_str().unwrap();
+        let paths = write_sketches(&filenames, &scaled_params(21, 1000, 42), outdir).unwrap();
+        assert!(paths.iter().all(|p| p.ends_with(".bsk")));
+
+        let sketches = read_sketches(&paths).unwrap();
+        let scale = sketches[0].sketch_params.hash_info().3.unwrap();
+        assert!((scale - 0.001).abs() < 1e-9);
+        // About one k-mer out of 1000 is kept
+        let expected = sketches[0].num_valid_kmers / 1000;
+        let kept = sketches[0].hashes.len() as u64;
+        assert!(kept > expected / 2 && kept < expected * 2);
+
+        let itself = finch::distance::distance(&sketches[0], &sketches[0], false).unwrap();
+        assert_eq!(itself.containment, 1.0);
+        assert!(matches!(
+            params_like(&sketches, 200).unwrap(),
+            SketchParams::Scaled { .. }
+        ));
+    }
+
+    #[test]
+    fn test_sketch_records_and_stream() {
+        let mut file = tempfile::Builder::new().suffix(".fa").tempfile().unwrap();
+        writeln!(file, "\n>r1 first record\nACGTACGTTGCA\n>r2\nTTGACCGATGCA").unwrap();
+        let path = file.path().to_str().unwrap();
+        let params = mash_params(5, 3, 1, 42);
+        let records = sketch_records(path, &params).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            (records[0].name.as_str(), records[0].comment.as_str()),
+            ("r1", "first record")
+        );
+        assert_eq!(records[1].seq_length, 12);
+        // The whole file as one genome
+        let genome = sketch_file_multi(path, std::slice::from_ref(&params)).unwrap();
+        assert_eq!(genome[0].seq_length, 24);
+
+        let mut json = Vec::new();
+        write_sketch_stream(&mut json, &records, SketchFormat::Json).unwrap();
+        let multi: MultiSketch = serde_json::from_slice(&json).unwrap();
+        assert_eq!(multi.to_sketches().unwrap().len(), 2);
+        let mut mash = Vec::new();
+        write_sketch_stream(&mut mash, &records, SketchFormat::Mash).unwrap();
+        let read = finch::serialization::read_mash_file(&mut mash.as_slice()).unwrap();
+        assert_eq!(read[1].name, "r2");
+
+        let scaled = sketch_records(path, &scaled_params(5, 2, 42)).unwrap();
+        assert!(write_sketch_stream(&mut Vec::new(), &scaled, SketchFormat::Mash).is_err());
+
+        let err = sketch_file_multi("test/sketches/bacam.fna.msh", &[params]).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::Input(_))));
+    }
+
+    #[test]
+    fn test_scan_file() {
+        let mut file = tempfile::Builder::new().suffix(".fq").tempfile().unwrap();
+        writeln!(file, "@r1\nACGTACGTNNGGCCGGCA\n+\nIIIIIIIIIIIIIIIIII").unwrap();
+        let path = file.path().to_str().unwrap();
+        let scan = scan_file(path, &[mash_params(5, 1, 1, 42)], &Default::default()).unwrap();
+        assert_eq!(scan.format, Some(Format::Fastq));
+        assert_eq!((scan.stats.id.as_str(), scan.stats.length), ("r1", 18));
+        assert_eq!((scan.stats.n_count, scan.stats.gc_count), (2, 11));
+        assert_eq!(scan.sketches.len(), 1);
+
+        // Short records are dropped before sketching, low complexity masked
+        let mut file = tempfile::Builder::new().suffix(".fa").tempfile().unwrap();
+        let unique = "ATGCGTACGTTAGCCTAGGCTAACGTTGCAGTCCGATGACTTAGCAGTGCATCGATCGGATCAA";
+        writeln!(file, ">c1\n{}{}\n>c2\nACGTACGTAC", unique, "A".repeat(64)).unwrap();
+        let filter = RecordFilter {
+            min_length: Some(100),
+            mask_low_complexity: true,
+        };
+        let scan = scan_file(file.path().to_str().unwrap(), &[], &filter).unwrap();
+        assert_eq!((scan.stats.records, scan.stats.length), (1, 128));
+        let expected = RecordCounts {
+            dropped_records: 1,
+            dropped_bases: 10,
+            masked_bases: 64,
+        };
+        assert_eq!(scan.filtered, expected);
+
+        // Statistics only, and no error for files that are not sequences
+        let scan = scan_file("test/bacam.fna", &[], &Default::default()).unwrap();
+        assert_eq!(scan.format, Some(Format::Fasta));
+        assert!(scan.sketches.is_empty());
+        let scan = scan_file("test/sketches/bacam.fna.msh", &[], &Default::default()).unwrap();
+        assert_eq!(scan.format, None);
+    }
+
+    #[test]
+    fn test_distinct_kmers() {
+        let mash = sketch_file_multi("test/bacam.fna", &[mash_params(21, 1000, 200, 42)]).unwrap();
+        let scaled = sketch_file_multi("test/bacam.fna", &[scaled_params(21, 1000, 42)]).unwrap();
+        // About one distinct 21-mer per base of a 4 Mb genome
+        for sketch in [&mash[0], &scaled[0]] {
+            let estimate = distinct_kmers(sketch) as f64;
+            assert!((estimate / 3_980_199.0 - 1.0).abs() < 0.1, "{}", estimate);
+        }
+    }
+
+    #[test]
+    fn test_sketch_file_multi() {
+        let params = [
+            mash_params(15, 1000, 200, 42),
+            mash_params(21, 1000, 200, 42),
+        ];
+        let sketches = sketch_file_multi("test/bacam.fna", &params).unwrap();
+        assert_eq!(sketches.len(), 2);
+        assert_eq!(sketches[0].sketch_params.k(), 15);
+        assert_eq!(sketches[1].sketch_params.k(), 21);
+
+        // Identical to sketching the file at each k separately
+        let single =
+            finch::sketch_files(&["test/bacam.fna"], &params[1], &sketches[1].filter_params)
+                .unwrap()
+                .remove(0);
+        let hashes = |s: &Sketch| s.hashes.iter().map(|h| h.hash).collect::<Vec<_>>();
+        assert_eq!(hashes(&single), hashes(&sketches[1]));
+    }
 }
@@ -0,0 +1,181 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::io::Write;
+
+use finch::serialization::Sketch;
+use serde_json::json;
+
+use crate::sketch;
+use crate::utils::{AssemblyStats, SeqStats};
+
+/// Output format of the per-genome statistics table
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatsFormat {
+    #[default]
+    Tsv,
+    /// JSON array with one object per genome
+    Json,
+}
+
+/// Statistics of one genome as reported in the table
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenomeStats {
+    pub name: String,
+    pub assembly: AssemblyStats,
+    /// Distinct k-mers estimated from the sketch, see [`sketch::distinct_kmers`]
+    pub distinct_kmers: Option<u64>,
+    /// Whether the genome size is an outlier, see [`crate::utils::detect_outliers`]
+    pub outlier: bool,
+}
+
+impl GenomeStats {
+    pub fn new(name: impl Into<String>, assembly: AssemblyStats, sketch: Option<&Sketch>) -> Self {
+        GenomeStats {
+            name: name.into(),
+            assembly,
+            distinct_kmers: sketch.map(sketch::distinct_kmers),
+            outlier: false,
+        }
+    }
+}
+
+/// Flag the genomes that are among the genome size `outliers`, by first
+/// record id and size
+pub fn mark_outliers(genomes: &mut [GenomeStats], outliers: &[SeqStats]) {
+    for genome in genomes {
+        genome.outlier = outliers.contains(&genome.assembly.seq_stats());
+    }
+}
+
+const TSV_HEADER: &str =
+    "genome\trecords\tlength\tn50\tl50\tlongest\tgc_percent\tn_percent\tambiguous_percent\tdistinct_kmers\toutlier";
+
+/// Write the statistics table
+pub fn write_stats(
+    out: &mut dyn Write,
+    genomes: &[GenomeStats],
+    format: StatsFormat,
+) -> anyhow::Result<()> {
+    match format {
+        StatsFormat::Tsv => {
+            writeln!(out, "{}", TSV_HEADER)?;
+            for genome in genomes {
+                let stats = &genome.assembly;
+                let composition = if stats.amino_acids {
+                    "NA\tNA\tNA".to_string()
+                } else {
+                    format!(
+                        "{:.2}\t{:.4}\t{:.4}",
+                        100.0 * stats.gc_fraction(),
+                        100.0 * stats.n_fraction(),
+                        100.0 * stats.ambiguous_fraction()
+                    )
+                };
+                writeln!(
+                    out,
+                    "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}",
+                    genome.name,
+                    stats.records,
+                    stats.length,
+                    stats.n50,
+                    stats.l50,
+                    stats.longest,
+                    composition,
+                    genome
+                        .distinct_kmers
+                        .map_or_else(|| "NA".to_string(), |n| n.to_string()),
+                    if genome.outlier { "yes" } else { "no" }
+                )?;
+            }
+        }
+        StatsFormat::Json => {
+            let rows: Vec<_> = genomes.iter().map(to_json).collect();
+            serde_json::to_writer_pretty(&mut *out, &rows)?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+/// JSON object of one genome, without base composition (null) for amino
+/// acids
+pub fn to_json(genome: &GenomeStats) -> serde_json::Value {
+    let stats = &genome.assembly;
+    let percent = |fraction: f64| (!stats.amino_acids).then_some(100.0 * fraction);
+    json!({
+        "genome": genome.name,
+        "records": stats.records,
+        "length": stats.length,
+        "n50": stats.n50,
+        "l50": stats.l50,
+        "longest": stats.longest,
+        "gc_percent": percent(stats.gc_fraction()),
+        "n_percent": percent(stats.n_fraction()),
+        "ambiguous_percent": percent(stats.ambiguous_fraction()),
+        "distinct_kmers": genome.distinct_kmers,
+        "outlier": genome.outlier,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_stats() {
+        let mut genomes = [
+            GenomeStats {
+                name: "a".to_string(),
+                assembly: AssemblyStats {
+                    id: "contig_a".to_string(),
+                    length: 100,
+                    records: 2,
+                    n50: 60,
+                    l50: 1,
+                    longest: 60,
+                    n_count: 10,
+                    ambiguous_count: 10,
+                    gc_count: 45,
+                    ..Default::default()
+                },
+                distinct_kmers: Some(80),
+                outlier: false,
+            },
+            GenomeStats::new("b", AssemblyStats::default(), None),
+            GenomeStats::new(
+                "c",
+                AssemblyStats {
+                    length: 300,
+                    records: 3,
+                    amino_acids: true,
+                    ..Default::default()
+                },
+                None,
+            ),
+        ];
+        mark_outliers(&mut genomes, &[("contig_a".to_string(), 100)]);
+        let mut tsv = Vec::new();
+        write_stats(&mut tsv, &genomes, StatsFormat::Tsv).unwrap();
+        let tsv = String::from_utf8(tsv).unwrap();
+        let lines: Vec<&str> = tsv.lines().collect();
+        assert_eq!(lines[0], TSV_HEADER);
+        assert_eq!(
+            lines[1],
+            "a\t2\t100\t60\t1\t60\t50.00\t10.0000\t10.0000\t80\tyes"
+        );
+        assert!(lines[2].ends_with("\tNA\tno"));
+        assert_eq!(lines[3], "c\t3\t300\t0\t0\t0\tNA\tNA\tNA\tNA\tno");
+
+        let mut json = Vec::new();
+        write_stats(&mut json, &genomes, StatsFormat::Json).unwrap();
+        let rows: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(rows[0]["l50"], 1);
+        assert_eq!(rows[0]["gc_percent"], 50.0);
+        assert_eq!(rows[0]["outlier"], true);
+        assert!(rows[1]["distinct_kmers"].is_null());
+        assert!(rows[2]["gc_percent"].is_null());
+    }
+}
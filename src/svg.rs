@@ -0,0 +1,433 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::collections::{BTreeSet, HashMap};
+use std::f64::consts::PI;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+use crate::error::Error;
+use crate::formats::escape_xml;
+use crate::tree::{self, Tree};
+
+/// Categorical palette used to color leaves
+const PALETTE: [&str; 10] = [
+    "#1f77b4", "#ff7f0e", "#2ca02c", "#d62728", "#9467bd", "#8c564b", "#e377c2", "#7f7f7f",
+    "#bcbd22", "#17becf",
+];
+const MARGIN: f64 = 20.0;
+const LEAF_SPACING: f64 = 18.0;
+const CHAR_WIDTH: f64 = 7.0;
+const FONT_SIZE: f64 = 12.0;
+
+/// Layout of the SVG tree
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Layout {
+    #[default]
+    Rectangular,
+    Circular,
+}
+
+/// Leaf colors with the legend they were derived from
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LeafColors {
+    /// Color of each leaf, indexed by leaf label
+    pub colors: HashMap<String, String>,
+    /// Metadata value and its color, in legend order
+    pub legend: Vec<(String, String)>,
+}
+
+/// Options controlling the SVG rendering of a tree
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvgOptions {
+    pub layout: Layout,
+    /// Width of the figure in pixels (also its height in circular layout)
+    pub width: f64,
+    /// Draw support values of internal nodes
+    pub support: bool,
+    pub colors: LeafColors,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions {
+            layout: Layout::Rectangular,
+            width: 800.0,
+            support: false,
+            colors: LeafColors::default(),
+        }
+    }
+}
+
+/// Read leaf colors from a metadata TSV.
+///
+/// The first line is a header, the first column holds the leaf labels and
+/// `column` names the column used to color the leaves. Each distinct value
+/// gets a color from a categorical palette.
+pub fn read_leaf_colors(path: &str, column: &str) -> anyhow::Result<LeafColors> {
+    let file = File::open(path).map_err(|e| Error::io(path, e))?;
+    let mut lines = BufReader::new(file).lines();
+
+    let header = lines
+        .next()
+        .transpose()?
+        .ok_or_else(|| Error::Input(format!("Metadata file {} is empty", path)))?;
+    let index = header
+        .split('\t')
+        .position(|h| h.trim() == column)
+        .ok_or_else(|| Error::Input(format!("Column '{}' not found in {}", column, path)))?;
+
+    let mut values: HashMap<String, String> = HashMap::new();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if let (Some(label), Some(value)) = (fields.first(), fields.get(index)) {
+            values.insert(label.trim().to_string(), value.trim().to_string());
+        }
+    }
+
+    let categories: BTreeSet<&String> = values.values().collect();
+    let legend: Vec<(String, String)> = categories
+        .into_iter()
+        .enumerate()
+        .map(|(i, value)| (value.clone(), PALETTE[i % PALETTE.len()].to_string()))
+        .collect();
+    let palette: HashMap<&String, &String> = legend.iter().map(|(v, c)| (v, c)).collect();
+    let colors = values
+        .iter()
+        .map(|(label, value)| (label.clone(), palette[value].clone()))
+        .collect();
+
+    Ok(LeafColors { colors, legend })
+}
+
+/// Render `tree` as a standalone SVG document
+pub fn render_svg(tree: &Tree, options: &SvgOptions) -> String {
+    let depth = tree.node_depths();
+    let max_depth = depth.iter().cloned().fold(0.0, f64::max);
+    // Horizontal space taken by leaf labels and the legend
+    let longest = |labels: &mut dyn Iterator<Item = &str>| {
+        labels.map(|l| l.chars().count()).max().unwrap_or(0) as f64 * CHAR_WIDTH
+    };
+    let mut reserved = longest(&mut tree.leaf_labels().into_iter());
+    if !options.colors.legend.is_empty() {
+        reserved += longest(&mut options.colors.legend.iter().map(|(v, _)| v.as_str())) + 24.0;
+    }
+
+    let mut body = String::new();
+    let (width, height, scale) = match options.layout {
+        Layout::Rectangular => {
+            draw_rectangular(tree, &depth, max_depth, reserved, options, &mut body)
+        }
+        Layout::Circular => draw_circular(tree, &depth, max_depth, reserved, options, &mut body),
+    };
+    draw_scale_bar(max_depth, scale, height, &mut body);
+    draw_legend(&options.colors.legend, width, &mut body);
+
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" \
+         viewBox=\"0 0 {:.0} {:.0}\" font-family=\"sans-serif\" font-size=\"{}\">",
+        width, height, width, height, FONT_SIZE
+    );
+    let _ = writeln!(out, "<rect width=\"100%\" height=\"100%\" fill=\"white\"/>");
+    out.push_str("<g stroke=\"black\" stroke-width=\"1\" fill=\"none\">\n");
+    out.push_str(&body);
+    out.push_str("</g>\n</svg>\n");
+    out
+}
+
+/// Position of each node along the leaf axis: leaf rank for leaves, midpoint
+/// of the first and last child for internal nodes
+fn leaf_positions(tree: &Tree) -> Vec<f64> {
+    let mut position = vec![0.0; tree.len()];
+    for (i, id) in tree.leaves().into_iter().enumerate() {
+        position[id] = i as f64;
+    }
+    for id in tree.postorder() {
+        let children = &tree.node(id).children;
+        if let (Some(&first), Some(&last)) = (children.first(), children.last()) {
+            position[id] = (position[first] + position[last]) / 2.0;
+        }
+    }
+    position
+}
+
+fn leaf_text<'a>(tree: &Tree, id: usize, options: &'a SvgOptions) -> (String, &'a str) {
+    let label = tree.node(id).label.as_deref().unwrap_or("");
+    let color = options
+        .colors
+        .colors
+        .get(label)
+        .map_or("black", String::as_str);
+    (escape_xml(label), color)
+}
+
+fn draw_rectangular(
+    tree: &Tree,
+    depth: &[f64],
+    max_depth: f64,
+    reserved: f64,
+    options: &SvgOptions,
+    out: &mut String,
+) -> (f64, f64, f64) {
+    let n_leaves = tree.leaves().len().max(1);
+    let width = options.width;
+    let height = 2.0 * MARGIN + (n_leaves - 1) as f64 * LEAF_SPACING + 40.0;
+    let tree_width = (width - 2.0 * MARGIN - reserved - 10.0).max(50.0);
+    let scale = if max_depth > 0.0 {
+        tree_width / max_depth
+    } else {
+        0.0
+    };
+    let position = leaf_positions(tree);
+    let x = |id: usize| MARGIN + depth[id] * scale;
+    let y = |id: usize| MARGIN + position[id] * LEAF_SPACING;
+
+    for id in tree.preorder() {
+        let node = tree.node(id);
+        if let Some(parent) = node.parent {
+            let _ = writeln!(
+                out,
+                "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\"/>",
+                x(parent),
+                y(id),
+                x(id),
+                y(id)
+            );
+        }
+        if let (Some(&first), Some(&last)) = (node.children.first(), node.children.last()) {
+            let _ = writeln!(
+                out,
+                "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\"/>",
+                x(id),
+                y(first),
+                x(id),
+                y(last)
+            );
+            if let (true, Some(support)) = (options.support, node.support) {
+                let _ = writeln!(
+                    out,
+                    "<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"end\" font-size=\"9\" \
+                     stroke=\"none\" fill=\"dimgray\">{}</text>",
+                    x(id) - 2.0,
+                    y(id) - 3.0,
+                    support
+                );
+            }
+        } else {
+            let (label, color) = leaf_text(tree, id, options);
+            let _ = writeln!(
+                out,
+                "<text x=\"{:.2}\" y=\"{:.2}\" stroke=\"none\" fill=\"{}\">{}</text>",
+                x(id) + 4.0,
+                y(id) + 4.0,
+                color,
+                label
+            );
+        }
+    }
+
+    (width, height, scale)
+}
+
+fn draw_circular(
+    tree: &Tree,
+    depth: &[f64],
+    max_depth: f64,
+    reserved: f64,
+    options: &SvgOptions,
+    out: &mut String,
+) -> (f64, f64, f64) {
+    let n_leaves = tree.leaves().len().max(1);
+    let width = options.width;
+    let height = width + 40.0;
+    let (cx, cy) = (width / 2.0, width / 2.0);
+    let radius = (width / 2.0 - MARGIN - reserved - 5.0).max(50.0);
+    let scale = if max_depth > 0.0 {
+        radius / max_depth
+    } else {
+        0.0
+    };
+    let angle: Vec<f64> = leaf_positions(tree)
+        .into_iter()
+        .map(|p| 2.0 * PI * p / n_leaves as f64)
+        .collect();
+    let point = |r: f64, a: f64| (cx + r * a.cos(), cy + r * a.sin());
+
+    for id in tree.preorder() {
+        let node = tree.node(id);
+        let r = depth[id] * scale;
+        if let Some(parent) = node.parent {
+            let (x1, y1) = point(depth[parent] * scale, angle[id]);
+            let (x2, y2) = point(r, angle[id]);
+            let _ = writeln!(
+                out,
+                "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\"/>",
+                x1, y1, x2, y2
+            );
+        }
+        if let (Some(&first), Some(&last)) = (node.children.first(), node.children.last()) {
+            let (start, end) = (angle[first], angle[last]);
+            let (x1, y1) = point(r, start);
+            let (x2, y2) = point(r, end);
+            let large_arc = u8::from(end - start > PI);
+            let _ = writeln!(
+                out,
+                "<path d=\"M {:.2} {:.2} A {:.2} {:.2} 0 {} 1 {:.2} {:.2}\"/>",
+                x1, y1, r, r, large_arc, x2, y2
+            );
+            if let (true, Some(support)) = (options.support, node.support) {
+                let (x, y) = point(r, angle[id]);
+                let _ = writeln!(
+                    out,
+                    "<text x=\"{:.2}\" y=\"{:.2}\" font-size=\"9\" stroke=\"none\" \
+                     fill=\"dimgray\">{}</text>",
+                    x + 2.0,
+                    y - 2.0,
+                    support
+                );
+            }
+        } else {
+            let (label, color) = leaf_text(tree, id, options);
+            let (x, y) = point(r + 4.0, angle[id]);
+            let degrees = angle[id].to_degrees();
+            // Keep labels on the left half readable
+            let (rotation, anchor) = if degrees > 90.0 && degrees < 270.0 {
+                (degrees - 180.0, "end")
+            } else {
+                (degrees, "start")
+            };
+            let _ = writeln!(
+                out,
+                "<text x=\"{:.2}\" y=\"{:.2}\" text-anchor=\"{}\" dominant-baseline=\"middle\" \
+                 transform=\"rotate({:.2} {:.2} {:.2})\" stroke=\"none\" fill=\"{}\">{}</text>",
+                x, y, anchor, rotation, x, y, color, label
+            );
+        }
+    }
+
+    (width, height, scale)
+}
+
+/// Scale bar of a round length, see [`tree::scale_bar_length`]
+fn draw_scale_bar(max_depth: f64, scale: f64, height: f64, out: &mut String) {
+    let Some((value, label)) = tree::scale_bar_length(max_depth).filter(|_| scale > 0.0) else {
+        return;
+    };
+    let y = height - MARGIN;
+    let _ = writeln!(
+        out,
+        "<line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\"/>",
+        MARGIN,
+        y,
+        MARGIN + value * scale,
+        y
+    );
+    let _ = writeln!(
+        out,
+        "<text x=\"{:.2}\" y=\"{:.2}\" stroke=\"none\" fill=\"black\">{}</text>",
+        MARGIN + value * scale + 4.0,
+        y + 4.0,
+        label
+    );
+}
+
+fn draw_legend(legend: &[(String, String)], width: f64, out: &mut String) {
+    let longest = legend
+        .iter()
+        .map(|(v, _)| v.chars().count())
+        .max()
+        .unwrap_or(0) as f64;
+    let x = width - MARGIN - longest * CHAR_WIDTH - 14.0;
+    for (i, (value, color)) in legend.iter().enumerate() {
+        let y = MARGIN + i as f64 * LEAF_SPACING;
+        let _ = writeln!(
+            out,
+            "<rect x=\"{:.2}\" y=\"{:.2}\" width=\"10\" height=\"10\" stroke=\"none\" fill=\"{}\"/>",
+            x,
+            y - 9.0,
+            color
+        );
+        let _ = writeln!(
+            out,
+            "<text x=\"{:.2}\" y=\"{:.2}\" stroke=\"none\" fill=\"black\">{}</text>",
+            x + 14.0,
+            y,
+            escape_xml(value)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn example() -> Tree {
+        Tree::from_newick("((A:0.1,B:0.2)90:0.05,C:0.3,D:0.4);").unwrap()
+    }
+
+    #[test]
+    fn test_render_svg_rectangular() {
+        let options = SvgOptions {
+            support: true,
+            ..Default::default()
+        };
+        let svg = render_svg(&example(), &options);
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\""));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        for label in ["A", "B", "C", "D"] {
+            assert!(svg.contains(&format!(">{}</text>", label)));
+        }
+        assert!(svg.contains(">90</text>"));
+        // Scale bar
+        assert!(svg.contains(">0.05</text>"));
+    }
+
+    #[test]
+    fn test_render_svg_circular() {
+        let options = SvgOptions {
+            layout: Layout::Circular,
+            ..Default::default()
+        };
+        let svg = render_svg(&example(), &options);
+        assert!(svg.contains("<path d=\"M "));
+        assert!(svg.contains("rotate("));
+        assert!(!svg.contains(">90</text>"));
+    }
+
+    #[test]
+    fn test_read_leaf_colors() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "genome\tspecies\tsize").unwrap();
+        writeln!(file, "A\tB. subtilis\t4").unwrap();
+        writeln!(file, "B\tB. cereus\t5").unwrap();
+        writeln!(file, "C\tB. subtilis\t4").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        let colors = read_leaf_colors(path, "species").unwrap();
+        assert_eq!(colors.legend.len(), 2);
+        assert_eq!(colors.legend[0].0, "B. cereus");
+        assert_eq!(colors.colors["A"], colors.colors["C"]);
+        assert_ne!(colors.colors["A"], colors.colors["B"]);
+
+        let svg = render_svg(
+            &example(),
+            &SvgOptions {
+                colors,
+                ..Default::default()
+            },
+        );
+        assert!(svg.contains(&format!("fill=\"{}\">A</text>", PALETTE[1])));
+
+        assert!(read_leaf_colors(path, "missing").is_err());
+    }
+}
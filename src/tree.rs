@@ -0,0 +1,795 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::error::Error;
+
+/// Index of a node in the [`Tree`] arena
+pub type NodeId = usize;
+
+/// A node of a phylogenetic tree.
+///
+/// Branch data (length, support) is stored on the child node and describes
+/// the edge leading to its parent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Node {
+    pub label: Option<String>,
+    pub parent: Option<NodeId>,
+    pub children: Vec<NodeId>,
+    /// Length of the branch leading to this node
+    pub length: Option<f64>,
+    /// Support value of the branch leading to this node
+    pub support: Option<f64>,
+    /// Newick comment attached to this node, without the square brackets
+    pub comment: Option<String>,
+}
+
+impl Node {
+    pub fn is_leaf(&self) -> bool {
+        self.children.is_empty()
+    }
+}
+
+/// A rooted phylogenetic tree stored as an arena of nodes
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tree {
+    nodes: Vec<Node>,
+    root: NodeId,
+}
+
+/// Label quoting strategy used when writing Newick
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Quoting {
+    /// Quote labels only when they contain Newick special characters
+    #[default]
+    Auto,
+    /// Always quote labels
+    Always,
+}
+
+/// Options controlling the Newick writer
+#[derive(Debug, Clone, PartialEq)]
+pub struct NewickOptions {
+    /// Number of decimals for branch lengths, shortest representation if `None`
+    pub precision: Option<usize>,
+    pub quoting: Quoting,
+    /// Write support values of internal nodes without label
+    pub support: bool,
+    /// Write node comments
+    pub comments: bool,
+}
+
+impl Default for NewickOptions {
+    fn default() -> Self {
+        NewickOptions {
+            precision: None,
+            quoting: Quoting::Auto,
+            support: true,
+            comments: true,
+        }
+    }
+}
+
+/// How negative branch lengths of NJ trees are handled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum NegativeBranches {
+    /// Leave negative lengths untouched
+    #[default]
+    Keep,
+    /// Set negative lengths to zero
+    Zero,
+    /// Set negative lengths to zero and subtract them from the longest sibling branch
+    Redistribute,
+}
+
+/// Changes made by [`Tree::fix_negative_branches`]
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct NegativeBranchSummary {
+    /// Number of branches whose length was changed
+    pub changed: usize,
+    /// Total absolute length moved or removed
+    pub shifted: f64,
+}
+
+impl Default for Tree {
+    fn default() -> Self {
+        Tree::new()
+    }
+}
+
+impl Tree {
+    /// Create a tree containing only a root node
+    pub fn new() -> Self {
+        Tree {
+            nodes: vec![Node::default()],
+            root: 0,
+        }
+    }
+
+    pub fn root(&self) -> NodeId {
+        self.root
+    }
+
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id]
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        &mut self.nodes[id]
+    }
+
+    /// Number of nodes in the arena
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Add `node` as the last child of `parent` and return its id
+    pub fn add_child(&mut self, parent: NodeId, mut node: Node) -> NodeId {
+        let id = self.nodes.len();
+        node.parent = Some(parent);
+        self.nodes.push(node);
+        self.nodes[parent].children.push(id);
+        id
+    }
+
+    /// Insert a new node on the branch above `child`, at `distance` from it.
+    ///
+    /// The branch length is split between the two resulting branches and the
+    /// new node takes the place of `child` among its parent's children.
+    pub fn split_edge(&mut self, child: NodeId, distance: f64) -> NodeId {
+        let parent = self.nodes[child]
+            .parent
+            .expect("the root has no branch to split");
+        let length = self.nodes[child].length;
+        let id = self.nodes.len();
+        self.nodes.push(Node {
+            parent: Some(parent),
+            children: vec![child],
+            length: length.map(|l| (l - distance).max(0.0)),
+            ..Default::default()
+        });
+        self.nodes[child].parent = Some(id);
+        self.nodes[child].length = Some(distance);
+        for c in self.nodes[parent].children.iter_mut() {
+            if *c == child {
+                *c = id;
+            }
+        }
+        id
+    }
+
+    pub fn is_leaf(&self, id: NodeId) -> bool {
+        self.nodes[id].is_leaf()
+    }
+
+    /// Node ids in preorder (parents before children)
+    pub fn preorder(&self) -> Vec<NodeId> {
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![self.root];
+        while let Some(id) = stack.pop() {
+            order.push(id);
+            stack.extend(self.nodes[id].children.iter().rev());
+        }
+        order
+    }
+
+    /// Node ids in postorder (children before parents)
+    pub fn postorder(&self) -> Vec<NodeId> {
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![self.root];
+        while let Some(id) = stack.pop() {
+            order.push(id);
+            stack.extend(self.nodes[id].children.iter());
+        }
+        order.reverse();
+        order
+    }
+
+    /// Leaf ids in preorder
+    pub fn leaves(&self) -> Vec<NodeId> {
+        self.preorder()
+            .into_iter()
+            .filter(|&id| self.is_leaf(id))
+            .collect()
+    }
+
+    /// Leaf labels in preorder, unlabelled leaves are skipped
+    pub fn leaf_labels(&self) -> Vec<&str> {
+        self.leaves()
+            .into_iter()
+            .filter_map(|id| self.nodes[id].label.as_deref())
+            .collect()
+    }
+
+    /// Distance of each node from the root, one unit per branch when the
+    /// tree has no branch lengths; negative lengths count as zero
+    pub fn node_depths(&self) -> Vec<f64> {
+        let order = self.preorder();
+        let has_lengths = order.iter().any(|&id| self.nodes[id].length.is_some());
+        let default_length = if has_lengths { 0.0 } else { 1.0 };
+        let mut depth = vec![0.0; self.len()];
+        for id in order {
+            if let Some(parent) = self.nodes[id].parent {
+                depth[id] =
+                    depth[parent] + self.nodes[id].length.unwrap_or(default_length).max(0.0);
+            }
+        }
+        depth
+    }
+
+    /// Apply a negative branch length policy to the whole tree.
+    ///
+    /// With [`NegativeBranches::Redistribute`], the distance between a node
+    /// and its longest sibling is kept whenever that sibling branch is long
+    /// enough to absorb the negative length.
+    pub fn fix_negative_branches(&mut self, policy: NegativeBranches) -> NegativeBranchSummary {
+        let mut summary = NegativeBranchSummary::default();
+        if policy == NegativeBranches::Keep {
+            return summary;
+        }
+
+        for id in self.postorder() {
+            let length = match self.nodes[id].length {
+                Some(l) if l < 0.0 => l,
+                _ => continue,
+            };
+            self.nodes[id].length = Some(0.0);
+            summary.changed += 1;
+            summary.shifted += length.abs();
+
+            if policy == NegativeBranches::Redistribute {
+                let sibling = self.nodes[id].parent.and_then(|parent| {
+                    self.nodes[parent]
+                        .children
+                        .iter()
+                        .copied()
+                        .filter(|&c| c != id)
+                        .max_by(|&a, &b| {
+                            let la = self.nodes[a].length.unwrap_or(0.0);
+                            let lb = self.nodes[b].length.unwrap_or(0.0);
+                            la.total_cmp(&lb)
+                        })
+                });
+                if let Some(sibling) = sibling {
+                    let sib_length = self.nodes[sibling].length.unwrap_or(0.0);
+                    self.nodes[sibling].length = Some((sib_length + length).max(0.0));
+                }
+            }
+        }
+
+        summary
+    }
+
+    /// Convert a speedytree NJ tree.
+    ///
+    /// speedytree trees are unrooted graphs where only leaves are labelled.
+    /// The tree is rooted at the first node with three neighbours, and
+    /// children are ordered like in `speedytree::to_newick`.
+    pub fn from_speedytree(graph: &speedytree::Tree) -> anyhow::Result<Self> {
+        let start = graph
+            .node_indices()
+            .find(|&n| graph.neighbors(n).count() == 3)
+            .ok_or_else(|| Error::Tree("NJ tree has no node with three neighbours".to_string()))?;
+
+        let mut tree = Tree::new();
+        let mut visited = vec![false; graph.node_count()];
+        visited[start.index()] = true;
+        let mut stack = vec![(start, tree.root)];
+
+        while let Some((gnode, id)) = stack.pop() {
+            let mut children: Vec<_> = graph
+                .neighbors(gnode)
+                .filter(|child| !visited[child.index()])
+                .collect();
+            children.reverse();
+            for child in children {
+                visited[child.index()] = true;
+                let length = graph
+                    .find_edge(gnode, child)
+                    .and_then(|e| graph.edge_weight(e))
+                    .copied();
+                let label = Some(graph[child].clone()).filter(|l| !l.is_empty());
+                let child_id = tree.add_child(
+                    id,
+                    Node {
+                        label,
+                        length,
+                        ..Default::default()
+                    },
+                );
+                stack.push((child, child_id));
+            }
+        }
+
+        Ok(tree)
+    }
+
+    /// Parse a tree in Newick format.
+    ///
+    /// Supports quoted labels (`'a label'`, with `''` for a literal quote),
+    /// comments in square brackets and support values given as numeric
+    /// internal node labels.
+    pub fn from_newick(newick: &str) -> anyhow::Result<Self> {
+        Parser::new(newick).parse()
+    }
+
+    /// Write the tree in Newick format
+    pub fn to_newick(&self, options: &NewickOptions) -> String {
+        let mut out = String::new();
+        let mut stack = vec![(self.root, 0)];
+
+        while let Some((id, i)) = stack.pop() {
+            let node = &self.nodes[id];
+            if node.is_leaf() {
+                self.write_node(&mut out, id, options);
+                continue;
+            }
+            if i == 0 {
+                out.push('(');
+            }
+            if i < node.children.len() {
+                if i > 0 {
+                    out.push(',');
+                }
+                stack.push((id, i + 1));
+                stack.push((node.children[i], 0));
+            } else {
+                out.push(')');
+                self.write_node(&mut out, id, options);
+            }
+        }
+
+        out.push(';');
+        out
+    }
+
+    fn write_node(&self, out: &mut String, id: NodeId, options: &NewickOptions) {
+        let node = &self.nodes[id];
+        if let Some(label) = &node.label {
+            out.push_str(&quote_label(label, options.quoting));
+        } else if let (true, Some(support), false) = (options.support, node.support, node.is_leaf())
+        {
+            let _ = write!(out, "{}", support);
+        }
+        // Comments come before the branch length, as in BEAST and FigTree
+        if let (true, Some(comment)) = (options.comments, &node.comment) {
+            let _ = write!(out, "[{}]", comment);
+        }
+        if let Some(length) = node.length {
+            out.push(':');
+            out.push_str(&format_length(length, options.precision));
+        }
+    }
+}
+
+/// Robinson–Foulds distance between two unrooted trees on the same leaves:
+/// the number of non-trivial bipartitions found in only one of them.
+///
+/// Returns the distance and its maximum, 2(n - 3) for n leaves.
+pub fn robinson_foulds(a: &Tree, b: &Tree) -> anyhow::Result<(usize, usize)> {
+    let mut labels: Vec<&str> = a.leaf_labels();
+    labels.sort_unstable();
+    let mut other = b.leaf_labels();
+    other.sort_unstable();
+    if labels != other {
+        return Err(Error::Tree("Trees do not have the same leaves".to_string()).into());
+    }
+
+    let index: HashMap<&str, usize> = labels.iter().enumerate().map(|(i, &l)| (l, i)).collect();
+    let (splits_a, splits_b) = (a.splits(&index), b.splits(&index));
+    let distance = splits_a.symmetric_difference(&splits_b).count();
+    Ok((distance, 2 * labels.len().saturating_sub(3)))
+}
+
+impl Tree {
+    /// Non-trivial bipartitions of the leaves, as membership vectors over
+    /// `index` oriented so that the first leaf is never included
+    fn splits(&self, index: &HashMap<&str, usize>) -> HashSet<Vec<bool>> {
+        let n = index.len();
+        let mut below: Vec<Vec<bool>> = vec![Vec::new(); self.len()];
+        let mut splits = HashSet::new();
+        for id in self.postorder() {
+            let node = &self.nodes[id];
+            let mut members = vec![false; n];
+            if let Some(&i) = node.label.as_deref().and_then(|l| index.get(l)) {
+                members[i] = true;
+            }
+            for &child in &node.children {
+                for (m, &c) in members.iter_mut().zip(&below[child]) {
+                    *m |= c;
+                }
+            }
+            let size = members.iter().filter(|&&m| m).count();
+            if node.parent.is_some() && size >= 2 && size + 2 <= n {
+                let split = if members[0] {
+                    members.iter().map(|m| !m).collect()
+                } else {
+                    members.clone()
+                };
+                splits.insert(split);
+            }
+            below[id] = members;
+        }
+        splits
+    }
+}
+
+impl FromStr for Tree {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Tree::from_newick(s)
+    }
+}
+
+/// Format a branch length with an optional number of decimals
+pub fn format_length(length: f64, precision: Option<usize>) -> String {
+    match precision {
+        Some(p) => format!("{:.*}", p, length),
+        None => format!("{}", length),
+    }
+}
+
+/// Round length (1, 2 or 5 times a power of ten) of a scale bar about a
+/// fifth of the tree depth, with its label; `None` for a flat tree
+pub fn scale_bar_length(max_depth: f64) -> Option<(f64, String)> {
+    if max_depth <= 0.0 {
+        return None;
+    }
+    let target = max_depth / 5.0;
+    let magnitude = 10f64.powf(target.log10().floor());
+    let value = [1.0, 2.0, 5.0]
+        .iter()
+        .map(|m| m * magnitude)
+        .take_while(|&v| v <= target)
+        .last()
+        .unwrap_or(magnitude);
+    let decimals = (-value.log10().floor()).max(0.0) as usize;
+    Some((value, format!("{:.*}", decimals, value)))
+}
+
+/// Quote a label for Newick or Nexus output when needed
+pub fn quote_label(label: &str, quoting: Quoting) -> String {
+    let needs_quotes = label
+        .chars()
+        .any(|c| c.is_whitespace() || "()[]':;,".contains(c));
+    if quoting == Quoting::Always || needs_quotes {
+        format!("'{}'", label.replace('\'', "''"))
+    } else {
+        label.to_string()
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Parser { input, pos: 0 }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.input[self.pos..].chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += c.len_utf8();
+        Some(c)
+    }
+
+    fn parse(mut self) -> anyhow::Result<Tree> {
+        let mut tree = Tree::new();
+        let mut current = tree.root;
+        let mut open: Vec<NodeId> = Vec::new();
+
+        loop {
+            let Some(c) = self.peek() else {
+                return Err(self.error("unexpected end of Newick string, missing ';'"));
+            };
+            match c {
+                c if c.is_whitespace() => {
+                    self.bump();
+                }
+                '(' => {
+                    self.bump();
+                    open.push(current);
+                    current = tree.add_child(current, Node::default());
+                }
+                ',' => {
+                    self.bump();
+                    let parent = *open
+                        .last()
+                        .ok_or_else(|| self.error("',' outside of parentheses"))?;
+                    current = tree.add_child(parent, Node::default());
+                }
+                ')' => {
+                    self.bump();
+                    current = open.pop().ok_or_else(|| self.error("unbalanced ')'"))?;
+                }
+                ':' => {
+                    self.bump();
+                    let length = self.parse_number()?;
+                    tree.nodes[current].length = Some(length);
+                }
+                '[' => {
+                    let comment = self.parse_comment()?;
+                    tree.nodes[current].comment = Some(comment);
+                }
+                ';' => {
+                    self.bump();
+                    if !open.is_empty() {
+                        return Err(self.error("unbalanced '('"));
+                    }
+                    break;
+                }
+                _ => {
+                    let label = if c == '\'' {
+                        self.parse_quoted()?
+                    } else {
+                        self.parse_unquoted()
+                    };
+                    let node = &mut tree.nodes[current];
+                    if node.label.is_some() {
+                        return Err(self.error("unexpected label"));
+                    }
+                    node.label = Some(label);
+                }
+            }
+        }
+
+        if self.input[self.pos..].trim().is_empty() {
+            // Numeric labels of internal nodes are support values
+            for node in tree.nodes.iter_mut().filter(|n| !n.is_leaf()) {
+                if let Some(support) = node.label.as_deref().and_then(|l| l.parse().ok()) {
+                    node.support = Some(support);
+                    node.label = None;
+                }
+            }
+            Ok(tree)
+        } else {
+            Err(self.error("trailing characters after ';'"))
+        }
+    }
+
+    fn parse_number(&mut self) -> anyhow::Result<f64> {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.bump();
+        }
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        {
+            self.bump();
+        }
+        let text = &self.input[start..self.pos];
+        text.parse()
+            .map_err(|_| self.error(&format!("invalid branch length '{}'", text)))
+    }
+
+    fn parse_comment(&mut self) -> anyhow::Result<String> {
+        self.bump();
+        let start = self.pos;
+        while let Some(c) = self.bump() {
+            if c == ']' {
+                return Ok(self.input[start..self.pos - 1].to_string());
+            }
+        }
+        Err(self.error("unterminated comment"))
+    }
+
+    fn parse_quoted(&mut self) -> anyhow::Result<String> {
+        self.bump();
+        let mut label = String::new();
+        while let Some(c) = self.bump() {
+            if c == '\'' {
+                if self.peek() == Some('\'') {
+                    self.bump();
+                    label.push('\'');
+                } else {
+                    return Ok(label);
+                }
+            } else {
+                label.push(c);
+            }
+        }
+        Err(self.error("unterminated quoted label"))
+    }
+
+    fn parse_unquoted(&mut self) -> String {
+        let start = self.pos;
+        while self
+            .peek()
+            .is_some_and(|c| !c.is_whitespace() && !"()[]':;,".contains(c))
+        {
+            self.bump();
+        }
+        self.input[start..self.pos].to_string()
+    }
+
+    fn error(&self, msg: &str) -> anyhow::Error {
+        Error::Tree(format!(
+            "Malformed Newick at position {}: {}",
+            self.pos, msg
+        ))
+        .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_newick_roundtrip() {
+        let newick = "((A:0.1,B:0.2)0.95:0.3,'C d':0.4,E:0.5);";
+        let tree = Tree::from_newick(newick).unwrap();
+        assert_eq!(tree.leaf_labels(), vec!["A", "B", "C d", "E"]);
+        assert_eq!(tree.to_newick(&NewickOptions::default()), newick);
+    }
+
+    #[test]
+    fn test_newick_support_and_comments() {
+        let tree = Tree::from_newick("((A,B)90[&note]:1,C)root;").unwrap();
+        let internal = tree.node(tree.root()).children[0];
+        assert_eq!(tree.node(internal).support, Some(90.0));
+        assert_eq!(tree.node(internal).label, None);
+        assert_eq!(tree.node(internal).comment.as_deref(), Some("&note"));
+        assert_eq!(tree.node(tree.root()).label.as_deref(), Some("root"));
+        assert_eq!(
+            tree.to_newick(&Default::default()),
+            "((A,B)90[&note]:1,C)root;"
+        );
+    }
+
+    #[test]
+    fn test_newick_quoted_labels() {
+        let tree = Tree::from_newick("('it''s',(B,'x,y'));").unwrap();
+        assert_eq!(tree.leaf_labels(), vec!["it's", "B", "x,y"]);
+        let options = NewickOptions {
+            quoting: Quoting::Always,
+            ..Default::default()
+        };
+        assert_eq!(tree.to_newick(&options), "('it''s',('B','x,y'));");
+    }
+
+    #[test]
+    fn test_newick_precision() {
+        let tree = Tree::from_newick("(A:0.123456,B:1,C:0.5);").unwrap();
+        let options = NewickOptions {
+            precision: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(tree.to_newick(&options), "(A:0.123,B:1.000,C:0.500);");
+    }
+
+    #[test]
+    fn test_newick_malformed() {
+        assert!(Tree::from_newick("(A,B,C)").is_err());
+        assert!(Tree::from_newick("(A,B,C;").is_err());
+        assert!(Tree::from_newick("(A,B),C);").is_err());
+        assert!(Tree::from_newick("(A:x,B,C);").is_err());
+        assert!(Tree::from_newick("('A,B,C);").is_err());
+    }
+
+    #[test]
+    fn test_fix_negative_branches() {
+        let newick = "((A:-0.1,B:0.3):0.2,C:-0.05,D:0.4);";
+
+        let mut tree = Tree::from_newick(newick).unwrap();
+        let summary = tree.fix_negative_branches(NegativeBranches::Keep);
+        assert_eq!(summary.changed, 0);
+        assert_eq!(tree.to_newick(&NewickOptions::default()), newick);
+
+        let mut tree = Tree::from_newick(newick).unwrap();
+        let summary = tree.fix_negative_branches(NegativeBranches::Zero);
+        assert_eq!(summary.changed, 2);
+        assert!((summary.shifted - 0.15).abs() < 1e-12);
+        assert_eq!(
+            tree.to_newick(&NewickOptions::default()),
+            "((A:0,B:0.3):0.2,C:0,D:0.4);"
+        );
+
+        let mut tree = Tree::from_newick(newick).unwrap();
+        let summary = tree.fix_negative_branches(NegativeBranches::Redistribute);
+        assert_eq!(summary.changed, 2);
+        let options = NewickOptions {
+            precision: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(
+            tree.to_newick(&options),
+            "((A:0.00,B:0.20):0.20,C:0.00,D:0.35);"
+        );
+    }
+
+    #[test]
+    fn test_split_edge() {
+        let mut tree = Tree::from_newick("((A:1,B:2):3,C:4);").unwrap();
+        let a = tree.leaves()[0];
+        let m = tree.split_edge(a, 0.25);
+        tree.add_child(
+            m,
+            Node {
+                label: Some("Q".to_string()),
+                length: Some(0.5),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            tree.to_newick(&NewickOptions::default()),
+            "(((A:0.25,Q:0.5):0.75,B:2):3,C:4);"
+        );
+    }
+
+    #[test]
+    fn test_from_speedytree() {
+        let matrix = speedytree::DistanceMatrix {
+            matrix: vec![
+                vec![0.0, 5.0, 9.0, 9.0, 8.0],
+                vec![5.0, 0.0, 10.0, 10.0, 9.0],
+                vec![9.0, 10.0, 0.0, 8.0, 7.0],
+                vec![9.0, 10.0, 8.0, 0.0, 3.0],
+                vec![8.0, 9.0, 7.0, 3.0, 0.0],
+            ],
+            names: ["a", "b", "c", "d", "e"].map(String::from).to_vec(),
+        };
+        let graph = speedytree::NeighborJoiningSolver::<speedytree::Canonical>::default(matrix)
+            .solve()
+            .unwrap();
+        let tree = Tree::from_speedytree(&graph).unwrap();
+
+        let mut labels = tree.leaf_labels();
+        labels.sort_unstable();
+        assert_eq!(labels, vec!["a", "b", "c", "d", "e"]);
+        let expected = Tree::from_newick(&speedytree::to_newick(&graph)).unwrap();
+        assert_eq!(
+            tree.to_newick(&NewickOptions::default()),
+            expected.to_newick(&NewickOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_robinson_foulds() {
+        let a = Tree::from_newick("((A,B),(C,D),(E,F));").unwrap();
+        // Same unrooted topology, rooted elsewhere
+        let b = Tree::from_newick("(A,B,((C,D),(E,F)));").unwrap();
+        assert_eq!(robinson_foulds(&a, &b).unwrap(), (0, 6));
+
+        let c = Tree::from_newick("((A,C),(B,D),(E,F));").unwrap();
+        assert_eq!(robinson_foulds(&a, &c).unwrap(), (4, 6));
+
+        let d = Tree::from_newick("((A,B),(C,D),(E,G));").unwrap();
+        assert!(robinson_foulds(&a, &d).is_err());
+    }
+
+    #[test]
+    fn test_node_depths_and_scale_bar() {
+        let tree = Tree::from_newick("((A:1,B:-1):1,C:4);").unwrap();
+        let depths = tree.node_depths();
+        let leaves: Vec<f64> = tree.leaves().iter().map(|&id| depths[id]).collect();
+        assert_eq!(leaves, [2.0, 1.0, 4.0]);
+        let cladogram = Tree::from_newick("((A,B),C);").unwrap();
+        assert_eq!(
+            cladogram.node_depths().iter().cloned().fold(0.0, f64::max),
+            2.0
+        );
+
+        assert_eq!(scale_bar_length(4.0), Some((0.5, "0.5".to_string())));
+        assert_eq!(scale_bar_length(60.0), Some((10.0, "10".to_string())));
+        assert_eq!(scale_bar_length(0.049), Some((0.005, "0.005".to_string())));
+        assert_eq!(scale_bar_length(0.0), None);
+    }
+}
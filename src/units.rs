@@ -0,0 +1,106 @@
+// Copyright 2024-2025 Anicet Ebou.
+// Licensed under the MIT license (http://opensource.org/licenses/MIT)
+// This file may not be copied, modified, or distributed except according
+// to those terms.
+
+/// Decimal units of sequence sizes, largest first
+const UNITS: &[(&str, f64)] = &[("Gb", 1e9), ("Mb", 1e6), ("Kb", 1e3)];
+
+/// Format a size in bases with the largest unit it reaches and `precision`
+/// decimals, e.g. "4.2 Mb"; sizes under 1 Kb are given in "bp"
+pub fn format_size(size: usize, precision: usize) -> String {
+    for &(unit, scale) in UNITS {
+        if size as f64 >= scale {
+            return format!("{:.*} {}", precision, size as f64 / scale, unit);
+        }
+    }
+    format!("{} bp", size)
+}
+
+/// Parse a human-readable size in bases such as "5M", "4.2Mb", "300kbp" or
+/// "1500". Units are decimal and case-insensitive.
+pub fn parse_size(text: &str) -> Result<usize, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let invalid = || format!("Invalid size: '{}'", text);
+    let digits = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" | "bp" => 0,
+        "k" | "kb" | "kbp" => 3,
+        "m" | "mb" | "mbp" => 6,
+        "g" | "gb" | "gbp" => 9,
+        other => {
+            return Err(format!(
+                "Invalid size unit '{}' in '{}', expected bp, K, M or G",
+                other, text
+            ))
+        }
+    };
+    // Integer arithmetic, so that e.g. 4.1M is exactly 4,100,000 bases
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if (whole.is_empty() && fraction.is_empty()) || fraction.contains('.') {
+        return Err(invalid());
+    }
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.len() > digits {
+        return Err(format!("Size is not a whole number of bases: '{}'", text));
+    }
+    let fraction = format!("{:0<width$}", fraction, width = digits);
+    format!("{}{}", whole, fraction)
+        .trim_start_matches('0')
+        .parse::<usize>()
+        .or_else(|e| match e.kind() {
+            std::num::IntErrorKind::Empty => Ok(0),
+            _ => Err(invalid()),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(950, 1), "950 bp");
+        assert_eq!(format_size(1_000, 1), "1.0 Kb");
+        assert_eq!(format_size(45_300, 1), "45.3 Kb");
+        assert_eq!(format_size(4_215_606, 1), "4.2 Mb");
+        assert_eq!(format_size(4_215_606, 3), "4.216 Mb");
+        assert_eq!(format_size(3_100_000_000, 0), "3 Gb");
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1500"), Ok(1500));
+        assert_eq!(parse_size("300bp"), Ok(300));
+        assert_eq!(parse_size("5M"), Ok(5_000_000));
+        assert_eq!(parse_size("4.2Mb"), Ok(4_200_000));
+        assert_eq!(parse_size("12 kbp"), Ok(12_000));
+        assert_eq!(parse_size("1.5g"), Ok(1_500_000_000));
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("-5M").is_err());
+        assert!(parse_size("5 Tb").is_err());
+        assert!(parse_size("1.5").is_err());
+        assert!(parse_size("1.2345K").is_err());
+        assert!(parse_size("1..5M").is_err());
+        assert!(parse_size(".").is_err());
+        // Decimal sizes are exact, without float rounding
+        assert_eq!(parse_size("4.1M"), Ok(4_100_000));
+        assert_eq!(parse_size("8.2M"), Ok(8_200_000));
+        assert_eq!(parse_size("16.1K"), Ok(16_100));
+        assert_eq!(parse_size("2.01M"), Ok(2_010_000));
+        assert_eq!(parse_size("1500.0"), Ok(1500));
+        assert_eq!(parse_size(".5K"), Ok(500));
+        assert_eq!(parse_size("0"), Ok(0));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for size in [999, 2_000, 5_000_000, 7_000_000_000] {
+            assert_eq!(parse_size(&format_size(size, 0)), Ok(size));
+        }
+    }
+}
@@ -1,35 +1,68 @@
 use crate::dist;
+use crate::error::Error;
+use crate::inputs;
+use crate::nj;
+use crate::progress;
+use crate::sketch;
+use crate::tree::Tree;
+use crate::units;
+use anyhow::Context;
 use std::fs::{self, File};
 use std::io::BufRead;
-use std::io::BufReader;
 use std::io::{self, Write};
 
+use needletail::parser::Format;
+use rayon::prelude::*;
+
+/// Compute a neighbor-joining tree from a distance matrix on `num_threads`
+/// threads
+pub fn compute_tree(
+    matrix: &speedytree::DistanceMatrix,
+    is_canonical: bool,
+    num_threads: usize,
+) -> anyhow::Result<Tree> {
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(num_threads)
+        .build()?;
+    let bar = progress::bar(matrix.size().saturating_sub(3) as u64, "Tree");
+    let graph = pool.install(|| nj::neighbor_joining(matrix, !is_canonical, || bar.inc(1)));
+    bar.finish_and_clear();
+    Tree::from_speedytree(&graph?)
+}
+
 pub fn compute_newick_tree(
     matrix: &speedytree::DistanceMatrix,
     is_canonical: bool,
     num_threads: usize,
 ) -> anyhow::Result<String> {
-    if is_canonical {
-        let tree =
-            speedytree::NeighborJoiningSolver::<speedytree::Canonical>::default(matrix.clone())
-                .solve()
-                .unwrap();
-        Ok(speedytree::to_newick(&tree))
+    let tree = compute_tree(matrix, is_canonical, num_threads)?;
+    Ok(tree.to_newick(&Default::default()))
+}
+
+/// Read a Newick tree from a file, or from stdin if `path` is "-"
+pub fn read_tree(path: &str) -> anyhow::Result<Tree> {
+    let newick = if path == "-" {
+        io::read_to_string(io::stdin())?
     } else {
-        let tree =
-            speedytree::NeighborJoiningSolver::<speedytree::RapidBtrees>::default(matrix.clone())
-                .set_chunk_size(std::cmp::max(matrix.size() / num_threads, 1))
-                .solve()
-                .unwrap();
-        Ok(speedytree::to_newick(&tree))
-    }
+        fs::read_to_string(path).map_err(|e| Error::io(path, e))?
+    };
+    Tree::from_newick(&newick).with_context(|| format!("Invalid tree file: {}", path))
+}
+
+/// Open FILE for writing, or stdout if no path is given
+pub fn open_output(path: Option<&str>) -> anyhow::Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => Box::new(io::BufWriter::new(
+            File::create(path).map_err(|e| Error::io(path, e))?,
+        )),
+        None => Box::new(io::stdout()),
+    })
 }
 
 pub fn output_tree(output: Option<String>, newick: String) -> anyhow::Result<()> {
     match output {
         Some(path) => {
-            let mut file = fs::File::create(path)?;
-            file.write_all(newick.as_bytes())?;
+            fs::write(&path, newick).map_err(|e| Error::io(path, e))?;
         }
         None => {
             writeln!(io::stdout(), "{}", newick)?;
@@ -51,86 +84,216 @@ pub fn manage_tempdir(
     }
 }
 
-pub fn is_fasta_format(path: &str) -> bool {
-    let file = File::open(path).expect("file should exists before opening.");
-    let reader = BufReader::new(file);
-    let mut lines = reader.lines();
-
-    while let Some(Ok(line)) = lines.next() {
-        let trimmed_line = line.trim();
-        if trimmed_line.is_empty() {
-            continue;
-        }
-        return match trimmed_line.chars().next() {
-            Some('>') => true,
-            _ => false,
-        };
-    }
-    false
+/// Whether the first non-blank character of `path` starts a FASTA header,
+/// peeked from the (decompressed) stream
+pub fn is_fasta_format(path: &str) -> anyhow::Result<bool> {
+    let mut reader = inputs::open(path)?;
+    let first = inputs::peek_format(&mut reader).map_err(|e| Error::io(path, e))?;
+    Ok(first == Some(b'>'))
 }
 
-pub fn is_multi_fasta(path: &str) -> bool {
-    let file = File::open(path).expect("file should be available");
-    let reader = BufReader::new(file);
+/// Whether `path` holds more than one FASTA record
+pub fn is_multi_fasta(path: &str) -> anyhow::Result<bool> {
+    let reader = inputs::open(path)?;
 
     let mut header_count = 0;
 
     for line in reader.lines() {
-        let line = line.expect("line should be parseable");
+        let line = line.map_err(|e| Error::io(path, e))?;
         if line.trim_start().starts_with('>') {
             header_count += 1;
             if header_count > 1 {
-                return true;
+                return Ok(true);
             }
         }
     }
-    false
+    Ok(false)
+}
+
+/// Sequence id with the genome length
+pub type SeqStats = (String, usize);
+
+/// Mean genome size of a set of genomes, summed in 64 bits so that large
+/// collections do not overflow
+pub fn mean_genome_size(stats: &[SeqStats]) -> u32 {
+    if stats.is_empty() {
+        return 0;
+    }
+    let total: u64 = stats.iter().map(|x| x.1 as u64).sum();
+    u32::try_from(total / stats.len() as u64).unwrap_or(u32::MAX)
 }
 
 // Return sequence id with its length
 pub fn get_seq_stats(path: &str) -> anyhow::Result<(String, usize)> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
+    Ok(get_assembly_stats(path)?.seq_stats())
+}
 
-    let mut total_len = 0;
-    let mut id = String::new();
-    for line in reader.lines() {
-        let line = line?;
-        let trimmed = line.trim();
+/// Assembly statistics of a FASTA file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AssemblyStats {
+    /// Identifier of the first record
+    pub id: String,
+    /// Total sequence length
+    pub length: usize,
+    /// Number of records
+    pub records: usize,
+    pub n50: usize,
+    /// Number of records at least N50 long
+    pub l50: usize,
+    /// Length of the longest record
+    pub longest: usize,
+    /// Number of N (or n) bases
+    pub n_count: usize,
+    /// Number of bases other than A, C, G and T, N included
+    pub ambiguous_count: usize,
+    /// Number of G, C and S bases
+    pub gc_count: usize,
+    /// Whether every record is a protein, whose residues are only counted
+    /// (base composition is left at zero)
+    pub amino_acids: bool,
+}
+
+impl AssemblyStats {
+    /// Fraction of N bases in the assembly
+    pub fn n_fraction(&self) -> f64 {
+        if self.length == 0 {
+            0.0
+        } else {
+            self.n_count as f64 / self.length as f64
+        }
+    }
 
-        if trimmed.is_empty() {
-            continue;
+    /// Fraction of bases other than A, C, G and T
+    pub fn ambiguous_fraction(&self) -> f64 {
+        if self.length == 0 {
+            0.0
+        } else {
+            self.ambiguous_count as f64 / self.length as f64
         }
+    }
 
-        if trimmed.starts_with('>') {
-            let tmp_id = trimmed[1..]
-                .split_whitespace()
-                .next()
-                .ok_or_else(|| anyhow::anyhow!("Malformed fasta header: {}", trimmed))?;
-            id = tmp_id.to_string();
+    /// GC content of the bases that are not N
+    pub fn gc_fraction(&self) -> f64 {
+        let called = self.length - self.n_count;
+        if called == 0 {
+            0.0
         } else {
-            total_len += trimmed.len();
+            self.gc_count as f64 / called as f64
+        }
+    }
+
+    /// (id, size) statistics
+    pub fn seq_stats(&self) -> SeqStats {
+        (self.id.clone(), self.length)
+    }
+}
+
+/// Collect [`AssemblyStats`] record by record while a file is streamed
+#[derive(Debug, Default)]
+pub struct StatsCollector {
+    id: Option<String>,
+    lengths: Vec<usize>,
+    n_count: usize,
+    ambiguous_count: usize,
+    gc_count: usize,
+    protein_records: usize,
+}
+
+impl StatsCollector {
+    /// Add a record given its header and its sequence, line breaks included
+    pub fn add_record(&mut self, header: &[u8], seq: &[u8]) {
+        self.add_id(header);
+        let mut length = 0;
+        for &base in seq {
+            match base.to_ascii_uppercase() {
+                b'\n' | b'\r' | b' ' | b'\t' => continue,
+                b'A' | b'T' => {}
+                b'G' | b'C' => self.gc_count += 1,
+                b'N' => {
+                    self.n_count += 1;
+                    self.ambiguous_count += 1;
+                }
+                b'S' => {
+                    self.gc_count += 1;
+                    self.ambiguous_count += 1;
+                }
+                _ => self.ambiguous_count += 1,
+            }
+            length += 1;
+        }
+        self.lengths.push(length);
+    }
+
+    /// Add a protein record, counting its residues but not its composition
+    pub fn add_protein_record(&mut self, header: &[u8], seq: &[u8]) {
+        self.add_id(header);
+        let length = seq.iter().filter(|c| !c.is_ascii_whitespace()).count();
+        self.lengths.push(length);
+        self.protein_records += 1;
+    }
+
+    fn add_id(&mut self, header: &[u8]) {
+        if self.id.is_none() {
+            let header = String::from_utf8_lossy(header);
+            self.id = Some(header.split_whitespace().next().unwrap_or("").to_string());
+        }
+    }
+
+    pub fn finish(self) -> AssemblyStats {
+        let (n50, l50) = n50_l50(&self.lengths);
+        AssemblyStats {
+            id: self.id.unwrap_or_default(),
+            length: self.lengths.iter().sum(),
+            records: self.lengths.len(),
+            n50,
+            l50,
+            longest: self.lengths.iter().copied().max().unwrap_or(0),
+            n_count: self.n_count,
+            ambiguous_count: self.ambiguous_count,
+            gc_count: self.gc_count,
+            amino_acids: self.protein_records > 0 && self.protein_records == self.lengths.len(),
+        }
+    }
+}
+
+/// Shortest record length such that records at least that long cover half
+/// of the total length
+pub fn n50(lengths: &[usize]) -> usize {
+    n50_l50(lengths).0
+}
+
+/// N50 and L50, the number of records at least N50 long
+pub fn n50_l50(lengths: &[usize]) -> (usize, usize) {
+    let mut sorted = lengths.to_vec();
+    sorted.sort_unstable_by(|a, b| b.cmp(a));
+    let total: usize = sorted.iter().sum();
+    let mut covered = 0;
+    for (i, len) in sorted.into_iter().enumerate() {
+        covered += len;
+        if 2 * covered >= total {
+            return (len, i + 1);
         }
     }
-    Ok((id, total_len))
+    (0, 0)
+}
+
+// Return the assembly statistics of a FASTA file
+pub fn get_assembly_stats(path: &str) -> anyhow::Result<AssemblyStats> {
+    let scan = sketch::scan_file(path, &[], &Default::default())?;
+    if scan.format.is_none() {
+        return Err(Error::Input(format!("Malformed fasta file: {}", path)).into());
+    }
+    Ok(scan.stats)
 }
 
+/// Genome size in bases, with its approximate value in the largest unit
+/// for sizes of at least 1 Kb, e.g. "4215606 bp (~4.2 Mb)"
 pub fn format_genome_size(size: usize) -> String {
-    let actual = format!("{} bp", size);
-
-    let approx = if size >= 1_000_000_000 {
-        let gb = size as f64 / 1_000_000_000.0;
-        format!("(~{:.1} Gb)", gb)
-    } else if size >= 1_000_000 {
-        let mb = size as f64 / 1_000_000.0;
-        format!("(~{:.1} Mb)", mb)
-    } else if size >= 1_000 {
-        let kb = size as f64 >= 1_000.0;
-        format!("(~{:.1} Kb)", kb)
+    if size >= 1_000 {
+        format!("{} bp (~{})", size, units::format_size(size, 1))
     } else {
-        "".to_string()
-    };
-    format!("{} {}", actual, approx)
+        format!("{} bp", size)
+    }
 }
 
 pub fn detect_outliers(
@@ -173,8 +336,8 @@ pub fn detect_outliers(
         let upper_bound = q3 + 3 * iqr / 2;
         outliers = data
             .iter()
-            .cloned()
             .filter(|x| x.1 < lower_bound || x.1 > upper_bound)
+            .cloned()
             .collect();
     }
 
@@ -182,33 +345,96 @@ pub fn detect_outliers(
 }
 
 pub fn validate_inputs(filenames: &[String]) -> anyhow::Result<()> {
-    if filenames.len() < 3 {
-        anyhow::bail!("At least three input FASTA files must be provided.");
+    check_input_count(filenames.len(), Operation::Tree)?;
+    validate_sequences(filenames)
+}
+
+/// Steps of a run that need a minimum number of genomes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Sketch,
+    Distances,
+    /// Neighbor-joining needs three taxa
+    Tree,
+}
+
+impl Operation {
+    /// Smallest number of genomes the operation works on
+    pub fn min_inputs(self) -> usize {
+        match self {
+            Operation::Sketch => 1,
+            Operation::Distances => 2,
+            Operation::Tree => 3,
+        }
     }
+}
+
+/// Check that there are enough genomes for `operation`
+pub fn check_input_count(count: usize, operation: Operation) -> anyhow::Result<()> {
+    if count >= operation.min_inputs() {
+        return Ok(());
+    }
+    let requirement = match operation {
+        Operation::Sketch => "Sketching needs at least one genome",
+        Operation::Distances => "Computing distances needs at least two genomes",
+        Operation::Tree => "Building a neighbor-joining tree needs at least three genomes",
+    };
+    Err(Error::Input(format!("{}, got {}", requirement, count)).into())
+}
+
+/// Check that every file is a single-sequence FASTA file
+pub fn validate_sequences(filenames: &[String]) -> anyhow::Result<()> {
+    let scans: Vec<sketch::Scan> = filenames
+        .par_iter()
+        .map(|f| sketch::scan_file(f, &[], &Default::default()))
+        .collect::<anyhow::Result<_>>()?;
+    check_sequences(filenames.iter().map(String::as_str).zip(&scans))
+}
+
+/// Why a scanned file cannot be used as a genome, if it cannot
+pub fn sequence_problem(scan: &sketch::Scan) -> Option<String> {
+    if scan.format != Some(Format::Fasta) {
+        Some("not a FASTA file".to_string())
+    } else if scan.stats.records > 1 && !scan.stats.amino_acids {
+        Some(format!(
+            "multi-sequence FASTA file ({} sequences)",
+            scan.stats.records
+        ))
+    } else {
+        None
+    }
+}
 
+/// Check from their scans that files are single-sequence FASTA files; a
+/// protein FASTA file is one genome whatever its number of records
+pub fn check_sequences<'a>(
+    scans: impl IntoIterator<Item = (&'a str, &'a sketch::Scan)>,
+) -> anyhow::Result<()> {
     let mut invalid = vec![];
     let mut multi_seq = vec![];
 
-    for file in filenames {
-        if !is_fasta_format(file) {
-            invalid.push(file.clone());
-        } else if is_multi_fasta(file) {
-            multi_seq.push(file.clone());
+    for (file, scan) in scans {
+        if scan.format != Some(Format::Fasta) {
+            invalid.push(file);
+        } else if scan.stats.records > 1 && !scan.stats.amino_acids {
+            multi_seq.push(file);
         }
     }
 
     if !invalid.is_empty() {
-        anyhow::bail!(
+        return Err(Error::Input(format!(
             "Only FASTA files are allowed. Invalid files: {}",
             invalid.join(", ")
-        );
+        ))
+        .into());
     }
 
     if !multi_seq.is_empty() {
-        anyhow::bail!(
+        return Err(Error::Input(format!(
             "Only single-sequence FASTA files are allowed. Multi-sequence files: {}",
             multi_seq.join(", ")
-        );
+        ))
+        .into());
     }
 
     Ok(())
@@ -220,11 +446,84 @@ mod tests {
 
     #[test]
     fn test_is_fasta_format_ok() {
-        assert!(is_fasta_format("test/bac168.fna"));
+        assert!(is_fasta_format("test/bacam.fna").unwrap());
+    }
+
+    #[test]
+    fn test_format_genome_size() {
+        assert_eq!(format_genome_size(950), "950 bp");
+        assert_eq!(format_genome_size(45_300), "45300 bp (~45.3 Kb)");
+        assert_eq!(format_genome_size(4_215_606), "4215606 bp (~4.2 Mb)");
+    }
+
+    #[test]
+    fn test_mean_genome_size() {
+        assert_eq!(mean_genome_size(&[]), 0);
+        // 1,000 genomes of 12 Mbp sum to 12 Gbp, beyond u32::MAX
+        let stats: Vec<SeqStats> = (0..1000).map(|i| (format!("g{}", i), 12_000_000)).collect();
+        assert_eq!(mean_genome_size(&stats), 12_000_000);
+        let stats = [("a".to_string(), 3), ("b".to_string(), 4)];
+        assert_eq!(mean_genome_size(&stats), 3);
+    }
+
+    #[test]
+    fn test_n50() {
+        assert_eq!(n50(&[2, 3, 4, 5, 6, 7, 8, 9, 10]), 8);
+        assert_eq!(n50(&[10]), 10);
+        assert_eq!(n50(&[]), 0);
+        assert_eq!(n50_l50(&[2, 3, 4, 5, 6, 7, 8, 9, 10]), (8, 3));
+    }
+
+    #[test]
+    fn test_stats_collector() {
+        let mut collector = StatsCollector::default();
+        collector.add_record(b"c1 chromosome", b"ACGTN\nGGCCA\r\n");
+        collector.add_record(b"c2", b"ATnR");
+        let stats = collector.finish();
+        assert_eq!(stats.id, "c1");
+        assert_eq!((stats.records, stats.length, stats.n50), (2, 14, 10));
+        assert_eq!((stats.l50, stats.longest), (1, 10));
+        assert_eq!((stats.n_count, stats.gc_count), (2, 6));
+        assert_eq!(stats.ambiguous_count, 3);
+        assert_eq!(stats.gc_fraction(), 0.5);
+    }
+
+    #[test]
+    fn test_get_assembly_stats() {
+        let stats = get_assembly_stats("test/bacam.fna").unwrap();
+        assert_eq!(stats.records, 1);
+        assert_eq!(stats.n50, stats.length);
+        assert!(stats.n_fraction() < 0.01);
     }
 
     #[test]
     fn test_is_fasta_format_not_ok() {
-        assert!(!is_fasta_format("test/test.fq"));
+        assert!(!is_fasta_format("test/test.fq").unwrap());
+    }
+
+    #[test]
+    fn test_check_input_count() {
+        assert!(check_input_count(1, Operation::Sketch).is_ok());
+        assert!(check_input_count(2, Operation::Distances).is_ok());
+        let err = check_input_count(1, Operation::Distances).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid input: Computing distances needs at least two genomes, got 1"
+        );
+        let err = check_input_count(2, Operation::Tree).unwrap_err();
+        assert!(err.to_string().contains("neighbor-joining tree"));
+        assert!(check_input_count(0, Operation::Sketch).is_err());
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let two = ["test/bacam.fna".to_string(), "test/bacsp.fna".to_string()];
+        let err = validate_inputs(&two).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::Input(_))));
+
+        // A missing file is reported, not a panic
+        let missing = ["test/missing.fna".to_string()];
+        let err = validate_sequences(&missing).unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Error::Io { .. })));
     }
 }